@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS project_settings (
+    owner VARCHAR(255) NOT NULL,
+    project_number INTEGER NOT NULL,
+    required_field TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (owner, project_number)
+);
@@ -0,0 +1,8 @@
+ALTER TABLE project_settings ADD COLUMN IF NOT EXISTS required_fields JSONB NOT NULL DEFAULT '[]';
+
+UPDATE project_settings
+SET required_fields = jsonb_build_array(
+    jsonb_build_object('name', required_field, 'type', 'single_select')
+);
+
+ALTER TABLE project_settings DROP COLUMN required_field;
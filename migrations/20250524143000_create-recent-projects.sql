@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS recent_projects (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    owner_kind VARCHAR(16) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    project_number INTEGER NOT NULL,
+    viewed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, owner_kind, owner, project_number)
+);
+
+CREATE INDEX IF NOT EXISTS recent_projects_user_id_viewed_at_index ON recent_projects (user_id, viewed_at DESC);
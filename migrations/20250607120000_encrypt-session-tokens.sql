@@ -0,0 +1,3 @@
+-- Sessions without a key id still hold plaintext tokens, the repository encrypts them on next use.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS token_key_id TEXT;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS data_key TEXT;
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS sessions_expires_at_index ON sessions (expires_at);
+CREATE INDEX IF NOT EXISTS sessions_last_used_at_index ON sessions (last_used_at);
@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS access_checked_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    organization VARCHAR(255),
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'triager', 'admin')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_roles_user_id_organization_index ON user_roles (user_id, COALESCE(organization, ''));
@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS refresh_claimed_at TIMESTAMPTZ;
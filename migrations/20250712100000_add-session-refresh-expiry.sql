@@ -0,0 +1,3 @@
+-- GitHub refresh tokens last six months, older sessions assume theirs was just issued.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS refresh_expires_at TIMESTAMPTZ NOT NULL DEFAULT now() + INTERVAL '6 months';
+ALTER TABLE sessions ALTER COLUMN refresh_expires_at DROP DEFAULT;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS sessions_expires_at_index;
+CREATE INDEX IF NOT EXISTS sessions_refresh_expires_at_index ON sessions (refresh_expires_at);
@@ -1,34 +1,181 @@
-use std::{error::Error, fmt::Display, sync::Arc};
+use std::{
+    env,
+    error::Error,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::extract::FromRef;
+use axum_extra::extract::cookie::Key;
 
 use crate::{
     AppSecrets,
     github::{Github, GithubService},
+    model::{AllowedGroup, Role},
     repository::{Postgres, Repository},
     routes::get_router,
+    scanner::scan_projects_every,
+    sweeper::sweep_sessions_every,
 };
 
+static DEFAULT_SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+static DEFAULT_PROJECT_SCAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub struct App {
     pub address: String,
     pub port: u32,
+    pub config: AppConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub secure_cookies: bool,
+    pub session_sweep_interval: Duration,
+    pub allowed_groups: Vec<AllowedGroup>,
+    pub installation_organizations: Vec<String>,
+    pub project_scan_interval: Duration,
+    pub default_role: Option<Role>,
+    pub admin_users: Vec<String>,
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+// Matches from_env with no variables set, so tests run against the production policy.
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            secure_cookies: true,
+            session_sweep_interval: DEFAULT_SESSION_SWEEP_INTERVAL,
+            allowed_groups: vec![],
+            installation_organizations: vec![],
+            project_scan_interval: DEFAULT_PROJECT_SCAN_INTERVAL,
+            default_role: Some(Role::Viewer),
+            admin_users: vec![],
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn from_env() -> Result<Self, AppStartError> {
+        let secure_cookies = env::var("SECURE_COOKIES").map_or(true, |secure| secure != "false");
+        let session_sweep_interval = interval_from_env(
+            "SESSION_SWEEP_INTERVAL_SECS",
+            DEFAULT_SESSION_SWEEP_INTERVAL,
+        )?;
+        let allowed_groups = env::var("ALLOWED_GROUPS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|group| !group.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(AppStartError::InvalidConfig)?;
+        // Only the project scan authenticates as the app installation, interactive routes use the user's token.
+        let installation_organizations = env::var("INSTALLATION_ORGS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|organization| !organization.is_empty())
+            .map(str::to_string)
+            .collect();
+        let project_scan_interval =
+            interval_from_env("PROJECT_SCAN_INTERVAL_SECS", DEFAULT_PROJECT_SCAN_INTERVAL)?;
+        // Users without a granted role fall back to the default role, "none" locks them out.
+        // Letting everyone update items takes an explicit DEFAULT_ROLE=triager.
+        let default_role = match env::var("DEFAULT_ROLE") {
+            Ok(role) if role.trim() == "none" => None,
+            Ok(role) => Some(role.parse().map_err(AppStartError::InvalidConfig)?),
+            Err(_) => AppConfig::default().default_role,
+        };
+        let admin_users = env::var("ADMIN_USERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|github_login| !github_login.is_empty())
+            .map(str::to_string)
+            .collect();
+        // X-Forwarded-For is only honoured on connections from these addresses.
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| {
+                proxy
+                    .parse()
+                    .map_err(|_| AppStartError::InvalidConfig(format!("Invalid proxy {proxy}")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(AppConfig {
+            secure_cookies,
+            session_sweep_interval,
+            allowed_groups,
+            installation_organizations,
+            project_scan_interval,
+            default_role,
+            admin_users,
+            trusted_proxies,
+        })
+    }
+}
+
+fn interval_from_env(name: &str, default: Duration) -> Result<Duration, AppStartError> {
+    match env::var(name) {
+        Ok(seconds) => seconds
+            .trim()
+            .parse()
+            .ok()
+            .filter(|seconds| *seconds > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| {
+                AppStartError::InvalidConfig(format!(
+                    "{name} must be a positive number of seconds, got {seconds}"
+                ))
+            }),
+        Err(_) => Ok(default),
+    }
 }
 
 #[derive(Debug)]
 pub enum AppStartError {
     FailedToBind(std::io::Error),
     FailedToStart,
+    InvalidCookieKey,
+    InvalidConfig(String),
 }
 
 pub struct AppState<T: Github, U: Repository> {
     pub secrets: &'static AppSecrets,
+    pub cookie_key: Key,
+    pub config: AppConfig,
     pub github: T,
     pub repository: U,
 }
 
+pub struct CookieKey(Key);
+
+impl From<CookieKey> for Key {
+    fn from(CookieKey(key): CookieKey) -> Self {
+        key
+    }
+}
+
+impl<T: Github, U: Repository> FromRef<Arc<AppState<T, U>>> for CookieKey {
+    fn from_ref(state: &Arc<AppState<T, U>>) -> Self {
+        CookieKey(state.cookie_key.clone())
+    }
+}
+
 impl Display for AppStartError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             AppStartError::FailedToBind(e) => write!(f, "Failed to bind {}", e),
             AppStartError::FailedToStart => write!(f, "Failed to start app"),
+            AppStartError::InvalidCookieKey => {
+                write!(f, "Cookie key must be at least 64 bytes long")
+            }
+            AppStartError::InvalidConfig(e) => write!(f, "Invalid config: {}", e),
         }
     }
 }
@@ -36,8 +183,12 @@ impl Display for AppStartError {
 impl Error for AppStartError {}
 
 impl App {
-    pub fn new(address: String, port: u32) -> App {
-        Self { address, port }
+    pub fn new(address: String, port: u32, config: AppConfig) -> App {
+        Self {
+            address,
+            port,
+            config,
+        }
     }
 
     pub async fn run(
@@ -50,14 +201,38 @@ impl App {
             .map_err(AppStartError::FailedToBind)?;
         tracing::info!("App started on {}:{}", self.address, self.port);
         let github = GithubService::new();
+        let cookie_key = Key::try_from(secrets.cookie_key.as_slice())
+            .map_err(|_| AppStartError::InvalidCookieKey)?;
+        if !self.config.installation_organizations.is_empty()
+            && secrets.github_app_private_key.is_empty()
+        {
+            return Err(AppStartError::InvalidConfig(
+                "Installation organizations require a GitHub App private key".to_string(),
+            ));
+        }
         let shared_state = Arc::new(AppState {
             secrets,
+            cookie_key,
+            config: self.config.clone(),
             github,
             repository,
         });
-        axum::serve(listener, get_router(shared_state))
-            .await
-            .map_err(|_| AppStartError::FailedToStart)?;
+        tokio::spawn(sweep_sessions_every(
+            shared_state.clone(),
+            self.config.session_sweep_interval,
+        ));
+        if !self.config.installation_organizations.is_empty() {
+            tokio::spawn(scan_projects_every(
+                shared_state.clone(),
+                self.config.project_scan_interval,
+            ));
+        }
+        axum::serve(
+            listener,
+            get_router(shared_state).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(|_| AppStartError::FailedToStart)?;
         Ok(())
     }
 }
@@ -1,43 +1,236 @@
-use std::sync::Arc;
+use std::{error::Error, fmt::Display, sync::Arc};
 
 use crate::{
-    extractors::ExtractSession,
-    github::{Github, Item},
-    model::Session,
-    repository::{CreateSessionRequest, Repository, RepositoryError},
+    crypto::{generate_api_token, hash_api_token},
+    extractors::{
+        AdminRole, ExtractClientInfo, ExtractForm, ExtractFormat, ExtractJson, ExtractPath,
+        ExtractQuery, ExtractRole, ExtractSession, ResponseFormat, TriagerRole, ViewerRole,
+        user_role,
+    },
+    github::{
+        BulkUpdateReport, DEFAULT_REQUIRED_FIELD, DateTime, FieldValue, Github, GraphqlError, Item,
+        ItemUpdateResult, ItemUpdateStatus, OwnerKind, ProjectField, ProjectSummary,
+    },
+    model::{
+        AllowedGroup, ApiScope, ApiToken, FieldType, ProjectSettings, RecentProject, RequiredField,
+        Role, RoleGrant, Session, SessionInfo, User,
+    },
+    repository::{
+        CreateApiTokenRequest, CreateSessionRequest, GrantRoleRequest, Repository, RepositoryError,
+    },
 };
 use askama::Template;
 use axum::{
     Json,
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{
+        Request, State,
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+    },
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
     response::{Html, IntoResponse, Redirect, Response},
 };
-use axum_extra::extract::{CookieJar, cookie::Cookie};
+use axum_extra::extract::{
+    SignedCookieJar,
+    cookie::{Cookie, SameSite},
+};
+use base64::prelude::*;
 use chrono::{TimeDelta, Utc};
 use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::types::Uuid;
+use time::Duration;
+use url::Url;
 
-use crate::app::AppState;
+use crate::app::{AppState, CookieKey};
 
 pub static SESSION_COOKIE: &str = "session";
-static CLIENT_ID: &str = "Iv23li3UZlzZ0kG6gw5s";
+static RECENT_PROJECTS_LIMIT: i64 = 10;
+static MAX_API_TOKEN_LIFETIME_DAYS: i64 = 365;
+static MAX_BULK_UPDATE_ITEMS: usize = 100;
+pub static CLIENT_ID: &str = "Iv23li3UZlzZ0kG6gw5s";
+static GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+static OAUTH_STATE_COOKIE: &str = "oauth_state";
+static OAUTH_STATE_MAX_AGE: Duration = Duration::minutes(10);
 
 #[derive(Debug)]
-pub struct AppError;
+pub enum AppError {
+    InvalidRequest(String),
+    Rejected(StatusCode, String),
+    Unauthorized(String),
+    Forbidden(String),
+    GithubRequestFailed(reqwest::Error),
+    GithubStatus(StatusCode),
+    GithubRateLimited(Option<DateTime>),
+    GithubNotFound(Vec<GraphqlError>),
+    GithubForbidden(Vec<GraphqlError>),
+    GraphqlErrors(Vec<GraphqlError>),
+    ProjectNotFound { owner: String, id: u32 },
+    OwnerNotFound(String),
+    Repository(RepositoryError),
+    Internal(String),
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Rejected(status, _) => *status,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::GithubStatus(
+                status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND),
+            ) => *status,
+            AppError::GithubRequestFailed(_)
+            | AppError::GithubStatus(_)
+            | AppError::GraphqlErrors(_) => StatusCode::BAD_GATEWAY,
+            AppError::GithubRateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::GithubNotFound(_)
+            | AppError::ProjectNotFound { .. }
+            | AppError::OwnerNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::GithubForbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Repository(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::InvalidRequest(e) => write!(f, "Invalid request: {}", e),
+            AppError::Rejected(_, e) => write!(f, "{}", e),
+            AppError::Unauthorized(e) => write!(f, "{}", e),
+            AppError::Forbidden(e) => write!(f, "{}", e),
+            AppError::GithubRequestFailed(e) => write!(f, "GitHub request failed: {}", e),
+            AppError::GithubStatus(status) => write!(f, "GitHub responded with {}", status),
+            AppError::GithubRateLimited(Some(reset_at)) => {
+                write!(f, "GitHub rate limit exceeded until {}", reset_at)
+            }
+            AppError::GithubRateLimited(None) => write!(f, "GitHub rate limit exceeded"),
+            AppError::GithubNotFound(errors) => {
+                write!(f, "Not found on GitHub: {}", graphql_messages(errors))
+            }
+            AppError::GithubForbidden(errors) => {
+                write!(f, "Forbidden by GitHub: {}", graphql_messages(errors))
+            }
+            AppError::GraphqlErrors(errors) => {
+                write!(f, "GitHub GraphQL errors: {}", graphql_messages(errors))
+            }
+            AppError::ProjectNotFound { owner, id } => {
+                write!(f, "Project {} of {} not found", id, owner)
+            }
+            AppError::OwnerNotFound(owner) => write!(f, "Owner {} not found", owner),
+            AppError::Repository(e) => write!(f, "{}", e),
+            AppError::Internal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn graphql_messages(errors: &[GraphqlError]) -> String {
+    errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::GithubRequestFailed(e) => Some(e),
+            AppError::Repository(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl From<RepositoryError> for AppError {
-    fn from(_: RepositoryError) -> Self {
-        AppError
+    fn from(e: RepositoryError) -> Self {
+        AppError::Repository(e)
     }
 }
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        AppError::GithubRequestFailed(e)
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<FormRejection> for AppError {
+    fn from(rejection: FormRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+#[derive(Clone)]
+struct ErrorMessage(String);
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Request failed".to_string(),
-        )
-            .into_response()
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("Request failed: {}", self);
+        } else {
+            tracing::warn!("Request failed: {}", self);
+        }
+        // Database, internal and transport failures are only detailed in the logs.
+        let message = match self {
+            _ if status == StatusCode::INTERNAL_SERVER_ERROR => "Request failed".to_string(),
+            AppError::GithubRequestFailed(_) => "GitHub request failed".to_string(),
+            _ => self.to_string(),
+        };
+        let mut response = (status, Json(json!({ "error": message }))).into_response();
+        if let AppError::GithubRateLimited(Some(reset_at)) = self {
+            let retry_after = (reset_at - Utc::now()).num_seconds().max(0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response.extensions_mut().insert(ErrorMessage(message));
+        response
+    }
+}
+
+// Errors are JSON by default, browsers get the same error as an HTML page.
+pub async fn render_errors(
+    ExtractFormat(format): ExtractFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let Some(ErrorMessage(message)) = response.extensions().get::<ErrorMessage>().cloned() else {
+        return response;
+    };
+    match format {
+        ResponseFormat::Json => response,
+        ResponseFormat::Html => {
+            let mut html_response = error_page(response.status(), &message);
+            if let Some(retry_after) = response.headers().get(RETRY_AFTER) {
+                html_response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, retry_after.clone());
+            }
+            html_response
+        }
     }
 }
 
@@ -45,43 +238,202 @@ impl IntoResponse for AppError {
 #[template(path = "login.html")]
 struct LoginTemplate {
     title: String,
-    client_id: &'static str,
+    authorize_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginParams {
+    return_to: Option<String>,
 }
 
-pub async fn login() -> Result<Html<String>, AppError> {
+pub async fn login<T: Github, U: Repository>(
+    ExtractQuery(params): ExtractQuery<LoginParams>,
+    jar: SignedCookieJar<CookieKey>,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<(SignedCookieJar<CookieKey>, Html<String>), AppError> {
+    let return_to = params
+        .return_to
+        .and_then(|return_to| same_origin_path(&return_to))
+        .unwrap_or_else(|| "/".to_string());
+    let oauth_state = random_token();
+    let code_verifier = random_token();
+    let mut authorize_url = Url::parse(GITHUB_AUTHORIZE_URL)
+        .map_err(|e| AppError::Internal(format!("Invalid authorize URL: {e}")))?;
+    authorize_url
+        .query_pairs_mut()
+        .append_pair("client_id", CLIENT_ID)
+        .append_pair("state", &oauth_state)
+        .append_pair("code_challenge", &code_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    let cookie = Cookie::build((
+        OAUTH_STATE_COOKIE,
+        format!("{oauth_state}:{code_verifier}:{return_to}"),
+    ))
+    .path("/callback")
+    .http_only(true)
+    .secure(state.config.secure_cookies)
+    .same_site(SameSite::Lax)
+    .max_age(OAUTH_STATE_MAX_AGE);
     let login_template = LoginTemplate {
         title: "Login".to_string(),
-        client_id: CLIENT_ID,
+        authorize_url: authorize_url.to_string(),
     };
-    Ok(Html(
-        login_template
-            .render()
-            .expect("Failed to render login template"),
+    Ok((
+        jar.add(cookie),
+        Html(
+            login_template
+                .render()
+                .expect("Failed to render login template"),
+        ),
     ))
 }
 
+fn random_token() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+fn code_challenge(code_verifier: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+// Returns the normalized path so the later redirect never carries unencoded control characters.
+fn same_origin_path(return_to: &str) -> Option<String> {
+    let base = Url::parse("http://localhost/").expect("Failed to parse base url");
+    let url = base
+        .join(return_to)
+        .ok()
+        .filter(|url| return_to.starts_with('/') && url.origin() == base.origin())?;
+    match url.query() {
+        Some(query) => Some(format!("{}?{}", url.path(), query)),
+        None => Some(url.path().to_string()),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    title: String,
+    message: String,
+}
+
+pub fn error_page(status: StatusCode, message: &str) -> Response {
+    let error_template = ErrorTemplate {
+        title: "Something went wrong".to_string(),
+        message: message.to_string(),
+    };
+    (
+        status,
+        Html(
+            error_template
+                .render()
+                .expect("Failed to render error template"),
+        ),
+    )
+        .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "access_denied.html")]
+struct AccessDeniedTemplate {
+    title: String,
+    github_login: String,
+    allowed_groups: Vec<String>,
+}
+
+pub fn access_denied_message(github_login: &str, allowed_groups: &[AllowedGroup]) -> String {
+    let allowed_groups = allowed_groups
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "The GitHub account {github_login} is no longer allowed to use Argonath, access is limited to members of {allowed_groups}."
+    )
+}
+
+fn access_denied_page(github_login: &str, allowed_groups: &[AllowedGroup]) -> Response {
+    let access_denied_template = AccessDeniedTemplate {
+        title: "Access denied".to_string(),
+        github_login: github_login.to_string(),
+        allowed_groups: allowed_groups.iter().map(ToString::to_string).collect(),
+    };
+    (
+        StatusCode::FORBIDDEN,
+        Html(
+            access_denied_template
+                .render()
+                .expect("Failed to render access denied template"),
+        ),
+    )
+        .into_response()
+}
+
 #[derive(Deserialize)]
 pub struct CallbackParams {
     code: String,
+    state: String,
 }
 
-pub async fn callback<T: Github, U: Repository>(
-    params: Query<CallbackParams>,
-    jar: CookieJar,
+pub async fn callback<T: Github + Sync, U: Repository>(
+    ExtractQuery(params): ExtractQuery<CallbackParams>,
+    jar: SignedCookieJar<CookieKey>,
+    ExtractClientInfo(client_info): ExtractClientInfo,
     State(state): State<Arc<AppState<T, U>>>,
-) -> Result<(CookieJar, Redirect), AppError> {
+) -> Result<Response, AppError> {
+    let Some((oauth_state, code_verifier, return_to)) =
+        jar.get(OAUTH_STATE_COOKIE).and_then(|cookie| {
+            let mut values = cookie.value().splitn(3, ':').map(str::to_string);
+            Some((values.next()?, values.next()?, values.next()?))
+        })
+    else {
+        tracing::warn!("Login callback without a pending login attempt");
+        return Ok(error_page(
+            StatusCode::BAD_REQUEST,
+            "Your login attempt has expired. Please try logging in again.",
+        ));
+    };
+    let jar = jar.remove(Cookie::build(OAUTH_STATE_COOKIE).path("/callback"));
+    if oauth_state != params.state {
+        tracing::warn!("Login callback with mismatched state");
+        return Ok((
+            jar,
+            error_page(
+                StatusCode::BAD_REQUEST,
+                "Your login attempt could not be verified. Please try logging in again.",
+            ),
+        )
+            .into_response());
+    }
     let res = state
         .github
-        .post_login_oauth_access_token(CLIENT_ID, &params.code, &state.secrets.client_secret)
+        .post_login_oauth_access_token(
+            CLIENT_ID,
+            &params.code,
+            &code_verifier,
+            &state.secrets.client_secret,
+        )
         .await?;
     let github_user = state.github.get_user(&res.access_token).await?;
+    let allowed_groups = &state.config.allowed_groups;
+    if !state
+        .github
+        .is_allowed(allowed_groups, &github_user.login, &res.access_token)
+        .await?
+    {
+        tracing::warn!(
+            "User {} is not a member of an allowed organization or team",
+            github_user.login
+        );
+        return Ok((jar, access_denied_page(&github_user.login, allowed_groups)).into_response());
+    }
     let user = state
         .repository
         .get_or_create_user(&github_user.login)
         .await?;
-    let expires_at = Utc::now()
-        .checked_add_signed(TimeDelta::seconds(res.expires_in))
-        .expect("Failed to add time");
+    tracing::info!("User {} logged in", user.github_login);
+    seed_admin(&state, &user).await?;
+    let expires_at = res.expires_at();
+    let refresh_expires_at = res.refresh_expires_at();
 
     let session = state
         .repository
@@ -90,139 +442,1664 @@ pub async fn callback<T: Github, U: Repository>(
             access_token: res.access_token,
             refresh_token: res.refresh_token,
             expires_at,
+            refresh_expires_at,
+            user_agent: client_info.user_agent,
+            ip_address: client_info.ip_address,
         })
         .await?;
     Ok((
-        jar.add(Cookie::new(SESSION_COOKIE, session.id)),
-        Redirect::temporary("/"),
-    ))
+        jar.add(session_cookie(
+            session.id,
+            session.refresh_expires_at,
+            state.config.secure_cookies,
+        )),
+        Redirect::temporary(&return_to),
+    )
+        .into_response())
 }
 
-pub async fn get_unmapped_items<T: Github, U: Repository>(
-    Path((org, project_id)): Path<(String, u32)>,
+// Configured admins only bootstrap the first admin, later grants are managed on /admin/roles.
+async fn seed_admin<T: Github, U: Repository>(
+    state: &AppState<T, U>,
+    user: &User,
+) -> Result<(), AppError> {
+    let is_configured_admin = state
+        .config
+        .admin_users
+        .iter()
+        .any(|admin_user| admin_user.eq_ignore_ascii_case(&user.github_login));
+    if !is_configured_admin {
+        return Ok(());
+    }
+    let admin_exists = state
+        .repository
+        .get_role_grants()
+        .await?
+        .iter()
+        .any(|grant| grant.organization.is_none() && grant.role == Role::Admin);
+    if admin_exists {
+        return Ok(());
+    }
+    tracing::info!("Seeding {} as the first admin", user.github_login);
+    state
+        .repository
+        .grant_role(GrantRoleRequest {
+            user_id: user.id,
+            organization: None,
+            role: Role::Admin,
+        })
+        .await?;
+    Ok(())
+}
+
+// The cookie lives as long as the refresh token, expired access tokens are refreshed on use.
+fn session_cookie(session_id: String, expires_at: DateTime, secure: bool) -> Cookie<'static> {
+    let max_age = (expires_at - Utc::now()).num_seconds().max(0);
+    Cookie::build((SESSION_COOKIE, session_id))
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(max_age))
+        .build()
+}
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomeTemplate {
+    title: String,
+    github_login: String,
+    is_admin: bool,
+    recent_projects: Vec<RecentProject>,
+    session_api_tokens: usize,
+}
+
+pub async fn home<T: Github, U: Repository>(
     ExtractSession(session): ExtractSession,
     State(state): State<Arc<AppState<T, U>>>,
-) -> Result<Json<Vec<Item>>, AppError> {
-    let projects = state
-        .github
-        .get_unmapped_items(org, project_id, &session.access_token)
+) -> Result<Html<String>, AppError> {
+    let user = state
+        .repository
+        .get_user(session.user_id)
+        .await?
+        .ok_or_else(|| AppError::Internal(format!("User {} not found", session.user_id)))?;
+    let recent_projects = state
+        .repository
+        .get_recent_projects(user.id, RECENT_PROJECTS_LIMIT)
         .await?;
-    Ok(Json(projects))
+    let is_admin = user_role(&state, user.id, None).await? == Some(Role::Admin);
+    let session_api_tokens = state
+        .repository
+        .get_user_api_tokens(user.id)
+        .await?
+        .iter()
+        .filter(|api_token| api_token.session_id == session.id)
+        .count();
+    let home_template = HomeTemplate {
+        title: "Argonath".to_string(),
+        github_login: user.github_login,
+        is_admin,
+        recent_projects,
+        session_api_tokens,
+    };
+    Ok(Html(
+        home_template
+            .render()
+            .expect("Failed to render home template"),
+    ))
 }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::Arc;
+#[derive(Template)]
+#[template(path = "projects.html")]
+struct ProjectsTemplate {
+    title: String,
+    owner_kind: &'static str,
+    owner: String,
+    projects: Vec<ProjectSummary>,
+    warnings: Vec<String>,
+}
 
-    use axum::{
-        extract::{Query, State},
-        response::IntoResponse,
-    };
-    use axum_extra::extract::CookieJar;
-    use mockall::predicate::eq;
+#[derive(Deserialize)]
+pub struct OwnerPath {
+    owner_kind: OwnerKind,
+    owner: String,
+}
 
-    use crate::{
-        AppSecrets,
-        app::AppState,
-        controller::{CallbackParams, SESSION_COOKIE, callback},
-        github::{Github, OauthResponse, UserResponse},
-        model::{Session, User},
-        repository::{CreateSessionRequest, MockRepository},
-    };
-
-    struct MockGithubService;
-    impl Github for MockGithubService {
-        fn new() -> MockGithubService {
-            MockGithubService {}
-        }
-        async fn post_login_oauth_access_token(
-            &self,
-            _: &str,
-            _: &str,
-            _: &str,
-        ) -> Result<OauthResponse, super::AppError> {
-            Ok(OauthResponse {
-                access_token: "access_token".to_string(),
-                refresh_token: "refresh_token".to_string(),
-            })
+pub async fn list_projects<T: Github, U: Repository>(
+    ExtractPath(path): ExtractPath<OwnerPath>,
+    ExtractFormat(format): ExtractFormat,
+    ExtractRole(session, _): ExtractRole<ViewerRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Response, AppError> {
+    let project_list = state
+        .github
+        .list_projects(path.owner_kind, path.owner.clone(), &session.access_token)
+        .await?;
+    match format {
+        ResponseFormat::Json => Ok(Json(project_list).into_response()),
+        ResponseFormat::Html => {
+            let projects_template = ProjectsTemplate {
+                title: format!("Projects of {}", path.owner),
+                owner_kind: path.owner_kind.as_str(),
+                owner: path.owner,
+                projects: project_list.projects,
+                warnings: project_list.warnings,
+            };
+            Ok(Html(
+                projects_template
+                    .render()
+                    .expect("Failed to render projects template"),
+            )
+            .into_response())
         }
+    }
+}
 
-        async fn get_user(&self, _: &str) -> Result<crate::github::UserResponse, super::AppError> {
-            Ok(UserResponse {
-                login: "user_login".to_owned(),
-            })
-        }
+#[derive(Deserialize)]
+pub struct ProjectPath {
+    owner_kind: OwnerKind,
+    owner: String,
+    id: u32,
+}
+
+#[derive(Template)]
+#[template(path = "unmapped_items.html")]
+struct UnmappedItemsTemplate {
+    title: String,
+    required_fields: String,
+    items: Vec<ItemRow>,
+    warnings: Vec<String>,
+}
+
+struct ItemRow {
+    title: String,
+    number: Option<i64>,
+    author: String,
+    avatar_url: Option<String>,
+    url: Option<String>,
+    age: String,
+    missing_fields: String,
+}
 
-        async fn get_unmapped_items(
-            &self,
-            _org: String,
-            _id: u32,
-            _access_token: &str,
-        ) -> Result<Vec<crate::github::Item>, super::AppError> {
-            todo!()
+impl From<Item> for ItemRow {
+    fn from(item: Item) -> Self {
+        ItemRow {
+            title: item.title,
+            number: item.number,
+            author: item.author.unwrap_or_else(|| "ghost".to_string()),
+            avatar_url: item.avatar_url,
+            url: item.url,
+            age: age(item.created_at),
+            missing_fields: item.missing_fields.join(", "),
         }
     }
+}
 
-    #[tokio::test]
-    async fn callback_sets_cookie_and_redirects() {
-        let github_mock = MockGithubService;
-        let mut repository_mock = MockRepository::new();
-        let params = Query(CallbackParams {
-            code: "code".to_string(),
-        });
-        let app_secrets = Box::new(AppSecrets {
-            client_secret: "client_secret".to_string(),
-            pg_url: "test_url".to_string(),
-        });
-        let app_secrets = Box::leak(app_secrets);
+fn age(created_at: DateTime) -> String {
+    let age = Utc::now().signed_duration_since(created_at);
+    if age.num_days() > 0 {
+        format!("{} days", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{} hours", age.num_hours())
+    } else {
+        format!("{} minutes", age.num_minutes())
+    }
+}
 
-        repository_mock
-            .expect_create_session()
-            .with(eq(CreateSessionRequest {
-                user_id: 1,
-                access_token: "access_token".to_string(),
-                refresh_token: "refresh_token".to_string(),
-            }))
-            .returning(|req| {
-                Ok(Session {
-                    id: "id".to_string(),
-                    user_id: req.user_id,
-                    access_token: req.access_token.clone(),
-                    refresh_token: req.refresh_token.clone(),
-                })
-            })
-            .times(1);
+#[derive(Deserialize)]
+pub struct LogoutParams {
+    #[serde(default)]
+    revoke_grant: bool,
+}
 
-        repository_mock
-            .expect_get_or_create_user()
-            .with(eq("user_login"))
-            .times(1)
-            .returning(|login| {
-                Ok(User {
-                    id: 1,
-                    github_login: login.to_string(),
-                })
-            });
+pub async fn logout<T: Github, U: Repository>(
+    jar: SignedCookieJar<CookieKey>,
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractForm(params): ExtractForm<LogoutParams>,
+) -> (SignedCookieJar<CookieKey>, Response) {
+    // The cookie is cleared even if the session outlives a failed delete.
+    let jar = jar.remove(Cookie::build(SESSION_COOKIE).path("/"));
+    match end_session(&state, &session, params.revoke_grant).await {
+        Ok(()) => (jar, Redirect::to("/login").into_response()),
+        Err(e) => (jar, e.into_response()),
+    }
+}
 
-        let app_state = AppState {
-            secrets: app_secrets,
-            github: github_mock,
-            repository: repository_mock,
-        };
-        let (jar, redirect) = callback(params, CookieJar::new(), State(Arc::new(app_state)))
+async fn end_session<T: Github, U: Repository>(
+    state: &AppState<T, U>,
+    session: &Session,
+    revoke_grant: bool,
+) -> Result<(), AppError> {
+    let session_id = Uuid::parse_str(&session.id)
+        .map_err(|e| AppError::Internal(format!("Invalid session id: {e}")))?;
+    state.repository.delete_session(session_id).await?;
+    if revoke_grant
+        && let Err(e) = state
+            .github
+            .delete_app_grant(
+                CLIENT_ID,
+                &state.secrets.client_secret,
+                &session.access_token,
+            )
             .await
-            .unwrap();
+    {
+        tracing::warn!(
+            "Failed to revoke the GitHub grant of user {}: {}",
+            session.user_id,
+            e
+        );
+    }
+    Ok(())
+}
 
-        assert_eq!(jar.get(SESSION_COOKIE).unwrap().value(), "id");
-        assert_eq!(
-            redirect
-                .into_response()
-                .headers()
-                .get(axum::http::header::LOCATION)
-                .unwrap()
-                .to_str()
-                .unwrap(),
-            "/"
+#[derive(Template)]
+#[template(path = "sessions.html")]
+struct SessionsTemplate {
+    title: String,
+    current_session_id: String,
+    sessions: Vec<SessionInfo>,
+    api_tokens: Vec<ApiToken>,
+}
+
+impl SessionsTemplate {
+    fn api_tokens_of(&self, session_id: &str) -> usize {
+        self.api_tokens
+            .iter()
+            .filter(|api_token| api_token.session_id == session_id)
+            .count()
+    }
+}
+
+pub async fn list_sessions<T: Github, U: Repository>(
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Html<String>, AppError> {
+    let sessions = state.repository.get_user_sessions(session.user_id).await?;
+    let api_tokens = state
+        .repository
+        .get_user_api_tokens(session.user_id)
+        .await?;
+    let sessions_template = SessionsTemplate {
+        title: "Sessions".to_string(),
+        current_session_id: session.id,
+        sessions,
+        api_tokens,
+    };
+    Ok(Html(
+        sessions_template
+            .render()
+            .expect("Failed to render sessions template"),
+    ))
+}
+
+pub async fn revoke_session<T: Github, U: Repository>(
+    ExtractPath(session_id): ExtractPath<String>,
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Redirect, AppError> {
+    let session_id = Uuid::parse_str(&session_id)
+        .map_err(|_| AppError::InvalidRequest(format!("Invalid session id {session_id}")))?;
+    state
+        .repository
+        .delete_user_session(session.user_id, session_id)
+        .await?;
+    Ok(Redirect::to("/sessions"))
+}
+
+pub async fn revoke_all_sessions<T: Github, U: Repository>(
+    jar: SignedCookieJar<CookieKey>,
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<(SignedCookieJar<CookieKey>, Redirect), AppError> {
+    state
+        .repository
+        .delete_user_sessions(session.user_id)
+        .await?;
+    Ok((
+        jar.remove(Cookie::build(SESSION_COOKIE).path("/")),
+        Redirect::to("/login"),
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "api_tokens.html")]
+struct ApiTokensTemplate {
+    title: String,
+    created_token: Option<String>,
+    api_tokens: Vec<ApiToken>,
+}
+
+pub async fn list_api_tokens<T: Github, U: Repository>(
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Html<String>, AppError> {
+    render_api_tokens(&state.repository, session.user_id, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiTokenParams {
+    name: String,
+    expires_in_days: i64,
+    #[serde(default)]
+    read: bool,
+    #[serde(default)]
+    write: bool,
+}
+
+pub async fn create_api_token<T: Github, U: Repository>(
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractForm(params): ExtractForm<CreateApiTokenParams>,
+) -> Result<Html<String>, AppError> {
+    if params.name.trim().is_empty() {
+        return Err(AppError::InvalidRequest(
+            "API tokens need a name".to_string(),
+        ));
+    }
+    if !(1..=MAX_API_TOKEN_LIFETIME_DAYS).contains(&params.expires_in_days) {
+        return Err(AppError::InvalidRequest(format!(
+            "API tokens must expire within 1 to {MAX_API_TOKEN_LIFETIME_DAYS} days"
+        )));
+    }
+    let scopes = [
+        (ApiScope::Read, params.read),
+        (ApiScope::Write, params.write),
+    ]
+    .into_iter()
+    .filter_map(|(scope, selected)| selected.then_some(scope))
+    .collect::<Vec<_>>();
+    if scopes.is_empty() {
+        return Err(AppError::InvalidRequest(
+            "API tokens need at least one scope".to_string(),
+        ));
+    }
+    let api_token = generate_api_token();
+    state
+        .repository
+        .create_api_token(CreateApiTokenRequest {
+            user_id: session.user_id,
+            session_id: Uuid::parse_str(&session.id)
+                .map_err(|e| AppError::Internal(format!("Invalid session id: {e}")))?,
+            name: params.name.trim().to_string(),
+            token_hash: hash_api_token(&api_token),
+            scopes,
+            // Tokens act through the session, so they can't outlive its refresh token
+            expires_at: (Utc::now() + TimeDelta::days(params.expires_in_days))
+                .min(session.refresh_expires_at),
+        })
+        .await?;
+    tracing::info!("User {} created API token {}", session.user_id, params.name);
+    render_api_tokens(&state.repository, session.user_id, Some(api_token)).await
+}
+
+pub async fn revoke_api_token<T: Github, U: Repository>(
+    ExtractPath(api_token_id): ExtractPath<String>,
+    ExtractSession(session): ExtractSession,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Redirect, AppError> {
+    let api_token_id = Uuid::parse_str(&api_token_id)
+        .map_err(|_| AppError::InvalidRequest(format!("Invalid API token id {api_token_id}")))?;
+    state
+        .repository
+        .delete_user_api_token(session.user_id, api_token_id)
+        .await?;
+    Ok(Redirect::to("/tokens"))
+}
+
+async fn render_api_tokens<U: Repository>(
+    repository: &U,
+    user_id: i32,
+    created_token: Option<String>,
+) -> Result<Html<String>, AppError> {
+    let api_tokens = repository.get_user_api_tokens(user_id).await?;
+    let api_tokens_template = ApiTokensTemplate {
+        title: "API tokens".to_string(),
+        created_token,
+        api_tokens,
+    };
+    Ok(Html(
+        api_tokens_template
+            .render()
+            .expect("Failed to render API tokens template"),
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "roles.html")]
+struct RolesTemplate {
+    title: String,
+    role_grants: Vec<RoleGrant>,
+}
+
+pub async fn list_roles<T: Github, U: Repository>(
+    ExtractRole(_, _): ExtractRole<AdminRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Html<String>, AppError> {
+    let role_grants = state.repository.get_role_grants().await?;
+    let roles_template = RolesTemplate {
+        title: "Roles".to_string(),
+        role_grants,
+    };
+    Ok(Html(
+        roles_template
+            .render()
+            .expect("Failed to render roles template"),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct GrantRoleParams {
+    github_login: String,
+    #[serde(default)]
+    organization: String,
+    role: Role,
+}
+
+pub async fn grant_role<T: Github, U: Repository>(
+    ExtractRole(session, _): ExtractRole<AdminRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractForm(params): ExtractForm<GrantRoleParams>,
+) -> Result<Redirect, AppError> {
+    if params.github_login.trim().is_empty() {
+        return Err(AppError::InvalidRequest(
+            "Roles need a GitHub login".to_string(),
+        ));
+    }
+    let user = state
+        .repository
+        .get_or_create_user(params.github_login.trim())
+        .await?;
+    let organization = role_organization(&params.organization);
+    tracing::info!(
+        "User {} granted the {} role to {} in {}",
+        session.user_id,
+        params.role.as_str(),
+        user.github_login,
+        organization.as_deref().unwrap_or("all organizations")
+    );
+    state
+        .repository
+        .grant_role(GrantRoleRequest {
+            user_id: user.id,
+            organization,
+            role: params.role,
+        })
+        .await?;
+    Ok(Redirect::to("/admin/roles"))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeRoleParams {
+    user_id: i32,
+    #[serde(default)]
+    organization: String,
+}
+
+pub async fn revoke_role<T: Github, U: Repository>(
+    ExtractRole(session, _): ExtractRole<AdminRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractForm(params): ExtractForm<RevokeRoleParams>,
+) -> Result<Redirect, AppError> {
+    let organization = role_organization(&params.organization);
+    tracing::info!(
+        "User {} revoked the role of user {} in {}",
+        session.user_id,
+        params.user_id,
+        organization.as_deref().unwrap_or("all organizations")
+    );
+    state
+        .repository
+        .revoke_role(params.user_id, organization)
+        .await?;
+    Ok(Redirect::to("/admin/roles"))
+}
+
+fn role_organization(organization: &str) -> Option<String> {
+    Some(organization.trim())
+        .filter(|organization| !organization.is_empty())
+        .map(str::to_string)
+}
+
+#[derive(Deserialize)]
+pub struct UnmappedItemsParams {
+    field: Option<String>,
+}
+
+pub async fn get_unmapped_items<T: Github, U: Repository>(
+    ExtractPath(project): ExtractPath<ProjectPath>,
+    ExtractQuery(params): ExtractQuery<UnmappedItemsParams>,
+    ExtractFormat(format): ExtractFormat,
+    ExtractRole(session, _): ExtractRole<ViewerRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Response, AppError> {
+    let required_fields = match params.field {
+        Some(field) => field
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<RequiredField>, _>>()
+            .map_err(AppError::InvalidRequest)?,
+        None => {
+            load_project_settings(&state.repository, &project.owner, project.id)
+                .await?
+                .required_fields
+        }
+    };
+    let unmapped_items = state
+        .github
+        .get_unmapped_items(
+            project.owner_kind,
+            project.owner.clone(),
+            project.id,
+            &required_fields,
+            &session.access_token,
+        )
+        .await?;
+    match format {
+        ResponseFormat::Json => Ok(Json(unmapped_items).into_response()),
+        ResponseFormat::Html => {
+            if let Err(e) = state
+                .repository
+                .record_project_view(
+                    session.user_id,
+                    RecentProject {
+                        owner_kind: project.owner_kind.as_str().to_string(),
+                        owner: project.owner.clone(),
+                        project_number: project_number(project.id)?,
+                        viewed_at: Utc::now(),
+                    },
+                )
+                .await
+            {
+                tracing::warn!("Failed to record project view: {}", e);
+            }
+            let unmapped_items_template = UnmappedItemsTemplate {
+                title: format!("{} project {}", project.owner, project.id),
+                required_fields: required_fields
+                    .iter()
+                    .map(|required| required.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                items: unmapped_items
+                    .items
+                    .into_iter()
+                    .map(ItemRow::from)
+                    .collect(),
+                warnings: unmapped_items.warnings,
+            };
+            Ok(Html(
+                unmapped_items_template
+                    .render()
+                    .expect("Failed to render unmapped items template"),
+            )
+            .into_response())
+        }
+    }
+}
+
+pub async fn get_project_fields<T: Github, U: Repository>(
+    ExtractPath(project): ExtractPath<ProjectPath>,
+    ExtractRole(session, _): ExtractRole<ViewerRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Json<Vec<ProjectField>>, AppError> {
+    let fields = state
+        .github
+        .get_project_fields(
+            project.owner_kind,
+            project.owner,
+            project.id,
+            &session.access_token,
+        )
+        .await?;
+    Ok(Json(fields))
+}
+
+#[derive(Deserialize)]
+pub struct ProjectItemPath {
+    owner_kind: OwnerKind,
+    owner: String,
+    id: u32,
+    item_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateItemFieldParams {
+    field_id: String,
+    value: FieldValue,
+}
+
+pub async fn update_item_field<T: Github, U: Repository>(
+    ExtractPath(item): ExtractPath<ProjectItemPath>,
+    ExtractRole(session, _): ExtractRole<TriagerRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractJson(params): ExtractJson<UpdateItemFieldParams>,
+) -> Result<StatusCode, AppError> {
+    let fields = state
+        .github
+        .get_project_fields(
+            item.owner_kind,
+            item.owner.clone(),
+            item.id,
+            &session.access_token,
+        )
+        .await?;
+    check_field_value(&fields, &params.field_id, &params.value)?;
+    let project_id = state
+        .github
+        .get_project_id(item.owner_kind, item.owner, item.id, &session.access_token)
+        .await?;
+    state
+        .github
+        .update_item_field(
+            &project_id,
+            &item.item_id,
+            &params.field_id,
+            params.value,
+            &session.access_token,
+        )
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct UpdateItemsFieldParams {
+    item_ids: Vec<String>,
+    field_id: String,
+    value: FieldValue,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+pub async fn update_items_field<T: Github + Sync, U: Repository>(
+    ExtractPath(project): ExtractPath<ProjectPath>,
+    ExtractRole(session, _): ExtractRole<TriagerRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractJson(params): ExtractJson<UpdateItemsFieldParams>,
+) -> Result<Json<BulkUpdateReport>, AppError> {
+    if params.item_ids.len() > MAX_BULK_UPDATE_ITEMS {
+        return Err(AppError::InvalidRequest(format!(
+            "At most {MAX_BULK_UPDATE_ITEMS} items can be updated at once"
+        )));
+    }
+    let fields = state
+        .github
+        .get_project_fields(
+            project.owner_kind,
+            project.owner.clone(),
+            project.id,
+            &session.access_token,
+        )
+        .await?;
+    check_field_value(&fields, &params.field_id, &params.value)?;
+    let project_id = state
+        .github
+        .get_project_id(
+            project.owner_kind,
+            project.owner,
+            project.id,
+            &session.access_token,
+        )
+        .await?;
+    let results = if params.dry_run {
+        let current_values = state
+            .github
+            .get_item_field_values(
+                &project_id,
+                &params.item_ids,
+                &params.field_id,
+                &session.access_token,
+            )
+            .await?;
+        params
+            .item_ids
+            .into_iter()
+            .map(|item_id| {
+                let status = match current_values.get(&item_id) {
+                    None => ItemUpdateStatus::Unknown,
+                    Some(Some(current)) if *current == params.value => ItemUpdateStatus::AlreadySet,
+                    Some(_) => ItemUpdateStatus::WouldUpdate,
+                };
+                ItemUpdateResult { item_id, status }
+            })
+            .collect()
+    } else {
+        state
+            .github
+            .update_items_field(
+                &project_id,
+                params.item_ids,
+                &params.field_id,
+                params.value,
+                &session.access_token,
+            )
+            .await
+    };
+    Ok(Json(BulkUpdateReport {
+        dry_run: params.dry_run,
+        results,
+    }))
+}
+
+fn check_field_value(
+    fields: &[ProjectField],
+    field_id: &str,
+    value: &FieldValue,
+) -> Result<(), AppError> {
+    let field = fields
+        .iter()
+        .find(|field| field.id == field_id)
+        .ok_or_else(|| AppError::InvalidRequest(format!("Unknown field {field_id}")))?;
+    let completed_iterations = field
+        .iteration_configuration
+        .iter()
+        .flat_map(|configuration| &configuration.completed_iterations);
+    let is_valid = match (field.field_type, value) {
+        (FieldType::SingleSelect, FieldValue::SingleSelectOptionId(option_id)) => {
+            field.options.iter().any(|option| option.id == *option_id)
+        }
+        (FieldType::Iteration, FieldValue::IterationId(iteration_id)) => {
+            field
+                .options
+                .iter()
+                .any(|option| option.id == *iteration_id)
+                || completed_iterations
+                    .into_iter()
+                    .any(|iteration| iteration.id == *iteration_id)
+        }
+        (FieldType::Number, FieldValue::Number(_))
+        | (FieldType::Date, FieldValue::Date(_))
+        | (FieldType::Text, FieldValue::Text(_)) => true,
+        _ => false,
+    };
+    if !is_valid {
+        return Err(AppError::InvalidRequest(format!(
+            "Invalid value for field {}",
+            field.name
+        )));
+    }
+    Ok(())
+}
+
+pub async fn get_project_settings<T: Github, U: Repository>(
+    ExtractPath(project): ExtractPath<ProjectPath>,
+    ExtractRole(_, _): ExtractRole<ViewerRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+) -> Result<Json<ProjectSettings>, AppError> {
+    let project_settings =
+        load_project_settings(&state.repository, &project.owner, project.id).await?;
+    Ok(Json(project_settings))
+}
+
+#[derive(Deserialize)]
+pub struct ProjectSettingsParams {
+    required_fields: Vec<RequiredField>,
+}
+
+pub async fn update_project_settings<T: Github, U: Repository>(
+    ExtractPath(project): ExtractPath<ProjectPath>,
+    ExtractRole(_, _): ExtractRole<AdminRole>,
+    State(state): State<Arc<AppState<T, U>>>,
+    ExtractJson(params): ExtractJson<ProjectSettingsParams>,
+) -> Result<Json<ProjectSettings>, AppError> {
+    let project_settings = state
+        .repository
+        .save_project_settings(ProjectSettings {
+            owner: project.owner,
+            project_number: project_number(project.id)?,
+            required_fields: params.required_fields,
+        })
+        .await?;
+    Ok(Json(project_settings))
+}
+
+pub async fn load_project_settings<U: Repository>(
+    repository: &U,
+    owner: &str,
+    project_id: u32,
+) -> Result<ProjectSettings, AppError> {
+    let project_number = project_number(project_id)?;
+    let project_settings = repository
+        .get_project_settings(owner, project_number)
+        .await?
+        .unwrap_or_else(|| ProjectSettings {
+            owner: owner.to_string(),
+            project_number,
+            required_fields: vec![RequiredField {
+                name: DEFAULT_REQUIRED_FIELD.to_string(),
+                field_type: FieldType::SingleSelect,
+            }],
+        });
+    Ok(project_settings)
+}
+
+fn project_number(project_id: u32) -> Result<i32, AppError> {
+    i32::try_from(project_id)
+        .map_err(|_| AppError::InvalidRequest(format!("Invalid project number {project_id}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        marker::PhantomData,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use axum::{
+        Json,
+        body::{Body, to_bytes},
+        extract::{FromRequest, FromRequestParts, State},
+        http::{
+            Request, StatusCode,
+            header::{CONTENT_TYPE, COOKIE, LOCATION, RETRY_AFTER, SET_COOKIE},
+        },
+        response::{Html, IntoResponse},
+    };
+    use axum_extra::extract::{
+        SignedCookieJar,
+        cookie::{Cookie, SameSite},
+    };
+    use chrono::{TimeDelta, Utc};
+    use mockall::predicate::eq;
+    use serde_json::{Value, json};
+    use sqlx::types::Uuid;
+    use time::Duration;
+
+    use crate::{
+        app::{AppConfig, CookieKey},
+        controller::{
+            AppError, CallbackParams, CreateApiTokenParams, LoginParams, LogoutParams,
+            OAUTH_STATE_COOKIE, OwnerPath, ProjectItemPath, ProjectPath, SESSION_COOKIE,
+            UnmappedItemsParams, UpdateItemFieldParams, UpdateItemsFieldParams, callback,
+            create_api_token, get_unmapped_items, list_projects, list_sessions, login, logout,
+            revoke_all_sessions, revoke_session, same_origin_path, seed_admin, update_item_field,
+            update_items_field,
+        },
+        extractors::{
+            ClientInfo, ExtractClientInfo, ExtractForm, ExtractFormat, ExtractJson, ExtractPath,
+            ExtractQuery, ExtractRole, ExtractSession, MinimumRole, ResponseFormat,
+        },
+        fixtures::{signed_jar, test_session, test_state},
+        github::{FieldValue, ItemUpdateStatus, OwnerKind},
+        model::{AllowedGroup, ApiScope, ApiToken, Role, RoleGrant, Session, SessionInfo, User},
+        repository::{MockRepository, RepositoryError},
+    };
+
+    #[tokio::test]
+    async fn callback_sets_cookie_and_redirects() {
+        let mut repository_mock = MockRepository::new();
+        let params = ExtractQuery(CallbackParams {
+            code: "code".to_string(),
+            state: "state".to_string(),
+        });
+
+        repository_mock
+            .expect_create_session()
+            .withf(|req| {
+                req.user_id == 1
+                    && req.access_token == "access_token"
+                    && req.refresh_token == "refresh_token"
+                    && req.user_agent.as_deref() == Some("user_agent")
+            })
+            .returning(|req| {
+                Ok(Session {
+                    id: "id".to_string(),
+                    user_id: req.user_id,
+                    access_token: req.access_token.clone(),
+                    refresh_token: req.refresh_token.clone(),
+                    expires_at: req.expires_at,
+                    refresh_expires_at: req.refresh_expires_at,
+                    last_used_at: Utc::now(),
+                    access_checked_at: Utc::now(),
+                })
+            })
+            .times(1);
+
+        repository_mock
+            .expect_get_or_create_user()
+            .with(eq("user_login"))
+            .times(1)
+            .returning(|login| {
+                Ok(User {
+                    id: 1,
+                    github_login: login.to_string(),
+                })
+            });
+
+        let app_state = test_state(AppConfig::default(), repository_mock);
+        let client_info = ExtractClientInfo(ClientInfo {
+            user_agent: Some("user_agent".to_string()),
+            ip_address: None,
+        });
+        let jar = signed_jar(
+            &app_state,
+            OAUTH_STATE_COOKIE,
+            "state:verifier:/org/acme/project/7?field=Status".to_string(),
+        )
+        .await;
+        let response = callback(params, jar, client_info, State(app_state.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "/org/acme/project/7?field=Status"
+        );
+        let session_cookie = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|cookie| Cookie::parse(cookie.to_str().unwrap().to_string()).unwrap())
+            .find(|cookie| cookie.name() == SESSION_COOKIE)
+            .unwrap();
+        assert_eq!(session_cookie.http_only(), Some(true));
+        assert_eq!(session_cookie.same_site(), Some(SameSite::Lax));
+        assert!(session_cookie.max_age().unwrap() > Duration::days(180));
+        let (mut parts, _) = Request::builder()
+            .header(COOKIE, session_cookie.stripped().to_string())
+            .body(())
+            .unwrap()
+            .into_parts();
+        let jar = SignedCookieJar::<CookieKey>::from_request_parts(&mut parts, &app_state)
+            .await
+            .unwrap();
+        assert_eq!(jar.get(SESSION_COOKIE).unwrap().value(), "id");
+    }
+
+    #[tokio::test]
+    async fn configured_admins_are_only_seeded_while_no_admin_exists() {
+        let state = |admin_exists: bool, seeded: usize| {
+            let mut repository_mock = MockRepository::new();
+            repository_mock.expect_get_role_grants().returning(move || {
+                Ok(admin_exists
+                    .then(|| RoleGrant {
+                        user_id: 2,
+                        github_login: "first_admin".to_string(),
+                        organization: None,
+                        role: Role::Admin,
+                    })
+                    .into_iter()
+                    .collect())
+            });
+            repository_mock
+                .expect_grant_role()
+                .withf(|req| req.user_id == 1 && req.organization.is_none())
+                .times(seeded)
+                .returning(|_| Ok(()));
+            test_state(
+                AppConfig {
+                    admin_users: vec!["Admin_Login".to_string()],
+                    ..AppConfig::default()
+                },
+                repository_mock,
+            )
+        };
+        let user = |id, github_login: &str| User {
+            id,
+            github_login: github_login.to_string(),
+        };
+
+        seed_admin(&state(false, 1), &user(1, "admin_login"))
+            .await
+            .unwrap();
+        seed_admin(&state(true, 0), &user(1, "admin_login"))
+            .await
+            .unwrap();
+        seed_admin(&state(false, 0), &user(1, "other_login"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn callback_rejects_mismatched_state() {
+        let params = ExtractQuery(CallbackParams {
+            code: "code".to_string(),
+            state: "forged_state".to_string(),
+        });
+        let app_state = test_state(AppConfig::default(), MockRepository::new());
+        let jar = signed_jar(
+            &app_state,
+            OAUTH_STATE_COOKIE,
+            "state:verifier:/".to_string(),
+        )
+        .await;
+        let response = callback(
+            params,
+            jar,
+            ExtractClientInfo(ClientInfo {
+                user_agent: None,
+                ip_address: None,
+            }),
+            State(app_state),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn callback_rejects_users_outside_allowed_groups() {
+        let params = ExtractQuery(CallbackParams {
+            code: "code".to_string(),
+            state: "state".to_string(),
+        });
+        let app_state = test_state(
+            AppConfig {
+                allowed_groups: vec![AllowedGroup::Team {
+                    organization: "allowed_org".to_string(),
+                    team: "other_team".to_string(),
+                }],
+                ..AppConfig::default()
+            },
+            MockRepository::new(),
+        );
+        let jar = signed_jar(
+            &app_state,
+            OAUTH_STATE_COOKIE,
+            "state:verifier:/".to_string(),
+        )
+        .await;
+        let response = callback(
+            params,
+            jar,
+            ExtractClientInfo(ClientInfo {
+                user_agent: None,
+                ip_address: None,
+            }),
+            State(app_state),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn return_to_must_stay_on_the_same_origin() {
+        assert_eq!(
+            same_origin_path("/org/acme/project/7?field=Status").as_deref(),
+            Some("/org/acme/project/7?field=Status")
+        );
+        assert_eq!(same_origin_path("https://evil.example/"), None);
+        assert_eq!(same_origin_path("//evil.example/"), None);
+        assert_eq!(same_origin_path("/\\evil.example/"), None);
+        assert_eq!(same_origin_path("org/acme/projects"), None);
+    }
+
+    #[tokio::test]
+    async fn return_to_is_normalized_before_it_is_stored() {
+        let state = test_state(AppConfig::default(), MockRepository::new());
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+        let jar = SignedCookieJar::<CookieKey>::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+
+        let (jar, _) = login(
+            ExtractQuery(LoginParams {
+                return_to: Some("/a\u{1}b".to_string()),
+            }),
+            jar,
+            State(state),
+        )
+        .await
+        .unwrap();
+
+        let oauth_state = jar.get(OAUTH_STATE_COOKIE).unwrap();
+        assert!(oauth_state.value().ends_with(":/a%01b"));
+    }
+
+    #[tokio::test]
+    async fn logout_deletes_session_and_clears_cookie() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+
+        repository_mock
+            .expect_delete_session()
+            .with(eq(session_id))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let app_state = test_state(AppConfig::default(), repository_mock);
+        let session = Session {
+            expires_at: Utc::now(),
+            ..test_session(session_id)
+        };
+        let jar = signed_jar(&app_state, SESSION_COOKIE, session_id.to_string()).await;
+        let (jar, response) = logout(
+            jar,
+            ExtractSession(session),
+            State(app_state),
+            ExtractForm(LogoutParams {
+                revoke_grant: false,
+            }),
+        )
+        .await;
+
+        assert!(jar.get(SESSION_COOKIE).is_none());
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "/login");
+    }
+
+    #[tokio::test]
+    async fn logout_clears_cookie_when_cleanup_fails() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+
+        let deletes = AtomicUsize::new(0);
+        repository_mock
+            .expect_delete_session()
+            .with(eq(session_id))
+            .times(2)
+            .returning(move |_| match deletes.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(()),
+                _ => Err(RepositoryError::FailedToDeleteSessionError),
+            });
+
+        let app_state = test_state(AppConfig::default(), repository_mock);
+        let session = || Session {
+            access_token: "revoked_access_token".to_string(),
+            expires_at: Utc::now(),
+            ..test_session(session_id)
+        };
+        let jar = || signed_jar(&app_state, SESSION_COOKIE, session_id.to_string());
+
+        let (grant_failed_jar, grant_failed) = logout(
+            jar().await,
+            ExtractSession(session()),
+            State(app_state.clone()),
+            ExtractForm(LogoutParams { revoke_grant: true }),
+        )
+        .await;
+        assert!(grant_failed_jar.get(SESSION_COOKIE).is_none());
+        assert_eq!(grant_failed.headers().get(LOCATION).unwrap(), "/login");
+
+        let (delete_failed_jar, delete_failed) = logout(
+            jar().await,
+            ExtractSession(session()),
+            State(app_state.clone()),
+            ExtractForm(LogoutParams {
+                revoke_grant: false,
+            }),
+        )
+        .await;
+        assert!(delete_failed_jar.get(SESSION_COOKIE).is_none());
+        assert_eq!(delete_failed.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    fn user_session(session_id: Uuid) -> ExtractSession {
+        ExtractSession(test_session(session_id))
+    }
+
+    #[tokio::test]
+    async fn sessions_page_lists_the_users_sessions() {
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_get_user_sessions()
+            .with(eq(1))
+            .times(1)
+            .returning(|_| {
+                Ok(vec![
+                    SessionInfo {
+                        id: Uuid::from_u128(1).to_string(),
+                        created_at: Utc::now(),
+                        last_used_at: Utc::now(),
+                        user_agent: Some("Firefox".to_string()),
+                        ip_address: Some("203.0.113.7".to_string()),
+                    },
+                    SessionInfo {
+                        id: Uuid::from_u128(2).to_string(),
+                        created_at: Utc::now(),
+                        last_used_at: Utc::now(),
+                        user_agent: None,
+                        ip_address: None,
+                    },
+                ])
+            });
+        repository_mock
+            .expect_get_user_api_tokens()
+            .with(eq(1))
+            .times(1)
+            .returning(|user_id| {
+                Ok(vec![ApiToken {
+                    id: Uuid::from_u128(3).to_string(),
+                    user_id,
+                    session_id: Uuid::from_u128(2).to_string(),
+                    name: "ci".to_string(),
+                    scopes: vec![ApiScope::Read],
+                    expires_at: Utc::now() + TimeDelta::days(1),
+                    created_at: Utc::now(),
+                }])
+            });
+
+        let Html(page) = list_sessions(
+            user_session(Uuid::from_u128(1)),
+            State(test_state(AppConfig::default(), repository_mock)),
+        )
+        .await
+        .unwrap();
+
+        assert!(page.contains("Firefox"));
+        assert!(page.contains("203.0.113.7"));
+        assert!(page.contains(&format!("/sessions/{}/revoke", Uuid::from_u128(2))));
+        assert!(!page.contains(&format!("/sessions/{}/revoke", Uuid::from_u128(1))));
+        assert!(page.contains("<td>1</td>"));
+    }
+
+    #[tokio::test]
+    async fn api_tokens_expire_with_the_sessions_refresh_token() {
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_create_api_token()
+            .withf(|req| {
+                let refresh_expires_in = req.expires_at - Utc::now();
+                refresh_expires_in > TimeDelta::days(179)
+                    && refresh_expires_in <= TimeDelta::days(180)
+            })
+            .times(1)
+            .returning(|req| {
+                Ok(ApiToken {
+                    id: Uuid::from_u128(3).to_string(),
+                    user_id: req.user_id,
+                    session_id: req.session_id.to_string(),
+                    name: req.name,
+                    scopes: req.scopes,
+                    expires_at: req.expires_at,
+                    created_at: Utc::now(),
+                })
+            });
+        repository_mock
+            .expect_get_user_api_tokens()
+            .returning(|_| Ok(vec![]));
+
+        let Html(page) = create_api_token(
+            user_session(Uuid::from_u128(1)),
+            State(test_state(AppConfig::default(), repository_mock)),
+            ExtractForm(CreateApiTokenParams {
+                name: "ci".to_string(),
+                expires_in_days: 365,
+                read: true,
+                write: false,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert!(page.contains("Copy your new token now"));
+    }
+
+    #[tokio::test]
+    async fn revoking_a_session_only_deletes_the_users_own_session() {
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_delete_user_session()
+            .with(eq(1), eq(Uuid::from_u128(2)))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let state = test_state(AppConfig::default(), repository_mock);
+
+        let redirect = revoke_session(
+            ExtractPath(Uuid::from_u128(2).to_string()),
+            user_session(Uuid::from_u128(1)),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            redirect.into_response().headers().get(LOCATION).unwrap(),
+            "/sessions"
+        );
+
+        let invalid = revoke_session(
+            ExtractPath("not-a-session".to_string()),
+            user_session(Uuid::from_u128(1)),
+            State(state),
+        )
+        .await;
+        assert!(matches!(invalid, Err(AppError::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn signing_out_everywhere_deletes_all_sessions_and_clears_cookie() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_delete_user_sessions()
+            .with(eq(1))
+            .times(1)
+            .returning(|_| Ok(()));
+        let state = test_state(AppConfig::default(), repository_mock);
+        let jar = signed_jar(&state, SESSION_COOKIE, session_id.to_string()).await;
+
+        let (jar, redirect) = revoke_all_sessions(jar, user_session(session_id), State(state))
+            .await
+            .unwrap();
+
+        assert!(jar.get(SESSION_COOKIE).is_none());
+        assert_eq!(
+            redirect.into_response().headers().get(LOCATION).unwrap(),
+            "/login"
+        );
+    }
+
+    fn role_session<R: MinimumRole>() -> ExtractRole<R> {
+        ExtractRole(
+            Session {
+                expires_at: Utc::now(),
+                ..test_session(Uuid::from_u128(1))
+            },
+            PhantomData,
+        )
+    }
+
+    fn acme_project() -> ExtractPath<ProjectPath> {
+        ExtractPath(ProjectPath {
+            owner_kind: OwnerKind::Organization,
+            owner: "acme".to_string(),
+            id: 7,
+        })
+    }
+
+    #[tokio::test]
+    async fn project_lists_report_warnings_and_missing_owners() {
+        let state = test_state(AppConfig::default(), MockRepository::new());
+        let owner = |owner: &str| {
+            ExtractPath(OwnerPath {
+                owner_kind: OwnerKind::Organization,
+                owner: owner.to_string(),
+            })
+        };
+
+        let html = list_projects(
+            owner("partial_owner"),
+            ExtractFormat(ResponseFormat::Html),
+            role_session(),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        let body = to_bytes(html.into_body(), usize::MAX).await.unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("Roadmap"));
+        assert!(page.contains("Resource not accessible by integration"));
+
+        let json = list_projects(
+            owner("partial_owner"),
+            ExtractFormat(ResponseFormat::Json),
+            role_session(),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        let body = to_bytes(json.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap()["warnings"],
+            json!(["Resource not accessible by integration"])
+        );
+
+        let missing = list_projects(
+            owner("missing_owner"),
+            ExtractFormat(ResponseFormat::Json),
+            role_session(),
+            State(state),
+        )
+        .await;
+        assert!(matches!(missing, Err(AppError::OwnerNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn only_rendered_project_pages_are_recorded_as_viewed() {
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_record_project_view()
+            .withf(|user_id, recent_project| {
+                *user_id == 1
+                    && recent_project.owner == "acme"
+                    && recent_project.project_number == 7
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let state = test_state(AppConfig::default(), repository_mock);
+        let params = || {
+            ExtractQuery(UnmappedItemsParams {
+                field: Some("Status".to_string()),
+            })
+        };
+
+        let html = get_unmapped_items(
+            acme_project(),
+            params(),
+            ExtractFormat(ResponseFormat::Html),
+            role_session(),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(html.status(), StatusCode::OK);
+
+        let json = get_unmapped_items(
+            acme_project(),
+            params(),
+            ExtractFormat(ResponseFormat::Json),
+            role_session(),
+            State(state.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(json.status(), StatusCode::OK);
+
+        let missing = get_unmapped_items(
+            ExtractPath(ProjectPath {
+                owner_kind: OwnerKind::Organization,
+                owner: "missing_owner".to_string(),
+                id: 7,
+            }),
+            params(),
+            ExtractFormat(ResponseFormat::Html),
+            role_session(),
+            State(state),
+        )
+        .await;
+        assert!(matches!(missing, Err(AppError::ProjectNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn bulk_dry_run_reports_which_items_would_change() {
+        let Json(report) = update_items_field(
+            acme_project(),
+            role_session(),
+            State(test_state(AppConfig::default(), MockRepository::new())),
+            ExtractJson(UpdateItemsFieldParams {
+                item_ids: vec![
+                    "PVTI_todo".to_string(),
+                    "PVTI_done".to_string(),
+                    "other_item".to_string(),
+                ],
+                field_id: "PVTSSF_status".to_string(),
+                value: FieldValue::SingleSelectOptionId("done".to_string()),
+                dry_run: true,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert!(report.dry_run);
+        assert_eq!(
+            report
+                .results
+                .iter()
+                .map(|result| result.status)
+                .collect::<Vec<_>>(),
+            vec![
+                ItemUpdateStatus::WouldUpdate,
+                ItemUpdateStatus::AlreadySet,
+                ItemUpdateStatus::Unknown,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn item_update_validates_the_field_value() {
+        let item = || {
+            ExtractPath(ProjectItemPath {
+                owner_kind: OwnerKind::Organization,
+                owner: "acme".to_string(),
+                id: 7,
+                item_id: "PVTI_todo".to_string(),
+            })
+        };
+        let update = async |field_id: &str, value| {
+            update_item_field(
+                item(),
+                role_session(),
+                State(test_state(AppConfig::default(), MockRepository::new())),
+                ExtractJson(UpdateItemFieldParams {
+                    field_id: field_id.to_string(),
+                    value,
+                }),
+            )
+            .await
+        };
+
+        let updated = update(
+            "PVTSSF_status",
+            FieldValue::SingleSelectOptionId("done".to_string()),
+        )
+        .await;
+        assert!(matches!(updated, Ok(StatusCode::NO_CONTENT)));
+        let unknown_option = update(
+            "PVTSSF_status",
+            FieldValue::SingleSelectOptionId("unknown".to_string()),
+        )
+        .await;
+        assert!(matches!(unknown_option, Err(AppError::InvalidRequest(_))));
+        let wrong_type = update("PVTSSF_status", FieldValue::Text("done".to_string())).await;
+        assert!(matches!(wrong_type, Err(AppError::InvalidRequest(_))));
+        let unknown_field = update(
+            "PVTF_unknown",
+            FieldValue::SingleSelectOptionId("done".to_string()),
+        )
+        .await;
+        assert!(matches!(unknown_field, Err(AppError::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn bulk_update_rejects_unknown_options_and_too_many_items() {
+        let unknown_option = update_items_field(
+            acme_project(),
+            role_session(),
+            State(test_state(AppConfig::default(), MockRepository::new())),
+            ExtractJson(UpdateItemsFieldParams {
+                item_ids: vec!["PVTI_todo".to_string()],
+                field_id: "PVTSSF_status".to_string(),
+                value: FieldValue::SingleSelectOptionId("unknown".to_string()),
+                dry_run: true,
+            }),
+        )
+        .await;
+        assert!(matches!(unknown_option, Err(AppError::InvalidRequest(_))));
+
+        let too_many_items = update_items_field(
+            acme_project(),
+            role_session(),
+            State(test_state(AppConfig::default(), MockRepository::new())),
+            ExtractJson(UpdateItemsFieldParams {
+                item_ids: (0..=100).map(|item| format!("PVTI_{item}")).collect(),
+                field_id: "PVTSSF_status".to_string(),
+                value: FieldValue::SingleSelectOptionId("done".to_string()),
+                dry_run: false,
+            }),
+        )
+        .await;
+        assert!(matches!(too_many_items, Err(AppError::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn app_errors_map_to_statuses_with_json_bodies() {
+        let not_found = AppError::ProjectNotFound {
+            owner: "acme".to_string(),
+            id: 7,
+        }
+        .into_response();
+        assert_eq!(not_found.status(), StatusCode::NOT_FOUND);
+        let body = to_bytes(not_found.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "Project 7 of acme not found" })
+        );
+
+        let rate_limited =
+            AppError::GithubRateLimited(Some(Utc::now() + TimeDelta::seconds(30))).into_response();
+        assert_eq!(rate_limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(rate_limited.headers().contains_key(RETRY_AFTER));
+
+        let repository_error =
+            AppError::from(RepositoryError::FailedToGetUserError).into_response();
+        assert_eq!(repository_error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(repository_error.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "Request failed" })
+        );
+
+        let request_failed = AppError::from(
+            reqwest::Client::new()
+                .get("http://internal.example:8080/graphql")
+                .header("X-Invalid", "\n")
+                .build()
+                .unwrap_err(),
+        )
+        .into_response();
+        assert_eq!(request_failed.status(), StatusCode::BAD_GATEWAY);
+        let body = to_bytes(request_failed.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "GitHub request failed" })
+        );
+    }
+
+    #[tokio::test]
+    async fn extractor_rejections_come_back_as_app_errors() {
+        let state = test_state(AppConfig::default(), MockRepository::new());
+        let request = Request::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from("{\"item_ids\": "))
+            .unwrap();
+        let Err(malformed_body) =
+            ExtractJson::<UpdateItemsFieldParams>::from_request(request, &state).await
+        else {
+            panic!("malformed body was accepted");
+        };
+        let malformed_body = malformed_body.into_response();
+        assert_eq!(malformed_body.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(malformed_body.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(
+            serde_json::from_slice::<Value>(&body).unwrap()["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Failed to parse the request body as JSON")
+        );
+
+        let (mut parts, _) = Request::builder()
+            .uri("/?owner_kind=bogus&owner=acme")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let Err(bogus_owner_kind) =
+            ExtractQuery::<OwnerPath>::from_request_parts(&mut parts, &state).await
+        else {
+            panic!("bogus owner kind was accepted");
+        };
+        let bogus_owner_kind = bogus_owner_kind.into_response();
+        assert_eq!(bogus_owner_kind.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            bogus_owner_kind.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
         );
     }
 }
@@ -0,0 +1,259 @@
+use std::{collections::HashMap, error::Error, fmt::Display};
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use base64::prelude::*;
+use sha2::{Digest, Sha256};
+
+static NONCE_LENGTH: usize = 12;
+static API_TOKEN_PREFIX: &str = "argonath_";
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum TokenKeysError {
+    InvalidTokenKeysError,
+    UnknownTokenKeyError(String),
+    FailedToEncryptError,
+    FailedToDecryptError,
+}
+impl Display for TokenKeysError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKeysError::InvalidTokenKeysError => {
+                write!(
+                    f,
+                    "Token keys must be comma separated <key id>:<base64 256 bit key> pairs"
+                )
+            }
+            TokenKeysError::UnknownTokenKeyError(key_id) => {
+                write!(f, "Token key {} is not configured", key_id)
+            }
+            TokenKeysError::FailedToEncryptError => write!(f, "Failed to encrypt tokens"),
+            TokenKeysError::FailedToDecryptError => write!(f, "Failed to decrypt tokens"),
+        }
+    }
+}
+impl Error for TokenKeysError {}
+
+pub struct EncryptedTokens {
+    pub key_id: String,
+    pub data_key: String,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+pub struct TokenKeys {
+    current_key_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl TokenKeys {
+    // The first key encrypts new tokens, the others are only kept to decrypt older sessions.
+    pub fn parse(token_keys: &str) -> Result<Self, TokenKeysError> {
+        let mut current_key_id = None;
+        let mut keys = HashMap::new();
+        for token_key in token_keys.split(',') {
+            let (key_id, key) = token_key
+                .trim()
+                .split_once(':')
+                .ok_or(TokenKeysError::InvalidTokenKeysError)?;
+            let key = BASE64_STANDARD
+                .decode(key)
+                .map_err(|_| TokenKeysError::InvalidTokenKeysError)?;
+            let cipher = Aes256Gcm::new_from_slice(&key)
+                .map_err(|_| TokenKeysError::InvalidTokenKeysError)?;
+            current_key_id.get_or_insert_with(|| key_id.to_string());
+            keys.insert(key_id.to_string(), cipher);
+        }
+        Ok(TokenKeys {
+            current_key_id: current_key_id.ok_or(TokenKeysError::InvalidTokenKeysError)?,
+            keys,
+        })
+    }
+
+    // Each ciphertext is bound to its session and column, so rows or columns cannot be swapped.
+    pub fn encrypt(
+        &self,
+        session_id: &str,
+        access_token: &str,
+        refresh_token: &str,
+    ) -> Result<EncryptedTokens, TokenKeysError> {
+        let key_encryption_key = self.key(&self.current_key_id)?;
+        let data_key = Aes256Gcm::generate_key(&mut OsRng);
+        let cipher = Aes256Gcm::new(&data_key);
+        Ok(EncryptedTokens {
+            key_id: self.current_key_id.clone(),
+            data_key: seal(key_encryption_key, &data_key, &aad(session_id, "data_key"))?,
+            access_token: seal(
+                &cipher,
+                access_token.as_bytes(),
+                &aad(session_id, "access_token"),
+            )?,
+            refresh_token: seal(
+                &cipher,
+                refresh_token.as_bytes(),
+                &aad(session_id, "refresh_token"),
+            )?,
+        })
+    }
+
+    pub fn decrypt(
+        &self,
+        session_id: &str,
+        encrypted_tokens: &EncryptedTokens,
+    ) -> Result<(String, String), TokenKeysError> {
+        let key_encryption_key = self.key(&encrypted_tokens.key_id)?;
+        let data_key = open(
+            key_encryption_key,
+            &encrypted_tokens.data_key,
+            &aad(session_id, "data_key"),
+        )?;
+        let cipher = Aes256Gcm::new_from_slice(&data_key)
+            .map_err(|_| TokenKeysError::FailedToDecryptError)?;
+        let access_token = open(
+            &cipher,
+            &encrypted_tokens.access_token,
+            &aad(session_id, "access_token"),
+        )?;
+        let refresh_token = open(
+            &cipher,
+            &encrypted_tokens.refresh_token,
+            &aad(session_id, "refresh_token"),
+        )?;
+        Ok((
+            String::from_utf8(access_token).map_err(|_| TokenKeysError::FailedToDecryptError)?,
+            String::from_utf8(refresh_token).map_err(|_| TokenKeysError::FailedToDecryptError)?,
+        ))
+    }
+
+    fn key(&self, key_id: &str) -> Result<&Aes256Gcm, TokenKeysError> {
+        self.keys
+            .get(key_id)
+            .ok_or_else(|| TokenKeysError::UnknownTokenKeyError(key_id.to_string()))
+    }
+}
+
+pub fn generate_api_token() -> String {
+    format!(
+        "{API_TOKEN_PREFIX}{}",
+        BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+    )
+}
+
+pub fn hash_api_token(api_token: &str) -> String {
+    BASE64_STANDARD.encode(Sha256::digest(api_token.as_bytes()))
+}
+
+fn aad(session_id: &str, column: &str) -> String {
+    format!("{session_id}:{column}")
+}
+
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8], aad: &str) -> Result<String, TokenKeysError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| TokenKeysError::FailedToEncryptError)?;
+    Ok(BASE64_STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+fn open(cipher: &Aes256Gcm, sealed: &str, aad: &str) -> Result<Vec<u8>, TokenKeysError> {
+    let sealed = BASE64_STANDARD
+        .decode(sealed)
+        .map_err(|_| TokenKeysError::FailedToDecryptError)?;
+    if sealed.len() < NONCE_LENGTH {
+        return Err(TokenKeysError::FailedToDecryptError);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| TokenKeysError::FailedToDecryptError)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+
+    use crate::crypto::{EncryptedTokens, TokenKeys};
+
+    fn token_key(key_id: &str, byte: u8) -> String {
+        format!("{key_id}:{}", BASE64_STANDARD.encode([byte; 32]))
+    }
+
+    #[test]
+    fn tokens_round_trip_without_leaking_plaintext() {
+        let token_keys = TokenKeys::parse(&token_key("v1", 1)).unwrap();
+
+        let encrypted_tokens = token_keys
+            .encrypt("session", "access_token", "refresh_token")
+            .unwrap();
+
+        assert_eq!(encrypted_tokens.key_id, "v1");
+        assert!(!encrypted_tokens.access_token.contains("access_token"));
+        assert_eq!(
+            token_keys.decrypt("session", &encrypted_tokens).unwrap(),
+            ("access_token".to_string(), "refresh_token".to_string())
+        );
+    }
+
+    #[test]
+    fn tokens_only_decrypt_for_their_session_and_column() {
+        let token_keys = TokenKeys::parse(&token_key("v1", 1)).unwrap();
+        let encrypted_tokens = token_keys
+            .encrypt("session", "access_token", "refresh_token")
+            .unwrap();
+
+        assert!(
+            token_keys
+                .decrypt("other_session", &encrypted_tokens)
+                .is_err()
+        );
+        let swapped_tokens = EncryptedTokens {
+            access_token: encrypted_tokens.refresh_token.clone(),
+            refresh_token: encrypted_tokens.access_token.clone(),
+            ..encrypted_tokens
+        };
+        assert!(token_keys.decrypt("session", &swapped_tokens).is_err());
+    }
+
+    #[test]
+    fn rotated_keys_still_decrypt_older_tokens() {
+        let old_token_keys = TokenKeys::parse(&token_key("v1", 1)).unwrap();
+        let encrypted_tokens = old_token_keys
+            .encrypt("session", "access_token", "refresh_token")
+            .unwrap();
+        let token_keys =
+            TokenKeys::parse(&format!("{},{}", token_key("v2", 2), token_key("v1", 1))).unwrap();
+
+        assert_eq!(
+            token_keys.decrypt("session", &encrypted_tokens).unwrap().0,
+            "access_token"
+        );
+        assert_eq!(
+            token_keys
+                .encrypt("session", "access_token", "refresh_token")
+                .unwrap()
+                .key_id,
+            "v2"
+        );
+        assert!(
+            TokenKeys::parse(&token_key("v2", 2))
+                .unwrap()
+                .decrypt("session", &encrypted_tokens)
+                .is_err()
+        );
+    }
+}
@@ -1,15 +1,41 @@
-use std::sync::Arc;
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
-use axum::{extract::FromRequestParts, http::request::Parts, response::Redirect};
-use axum_extra::extract::CookieJar;
-use chrono::Utc;
+use axum::{
+    extract::{ConnectInfo, FromRequest, FromRequestParts, RawPathParams},
+    http::{
+        Method,
+        header::{ACCEPT, AUTHORIZATION, USER_AGENT},
+        request::Parts,
+    },
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::SignedCookieJar;
+use chrono::{TimeDelta, Utc};
 use sqlx::types::Uuid;
+use url::form_urlencoded;
 
 use crate::{
-    app::AppState, controller::SESSION_COOKIE, github::Github, model::Session,
-    repository::Repository,
+    app::{AppState, CookieKey},
+    controller::{AppError, CLIENT_ID, SESSION_COOKIE, access_denied_message},
+    crypto::hash_api_token,
+    github::Github,
+    model::{ApiScope, Role, Session},
+    repository::{Repository, RepositoryError, TouchSessionRequest, UpdateSessionTokensRequest},
 };
 
+static SESSION_TOUCH_INTERVAL: TimeDelta = TimeDelta::minutes(1);
+static ACCESS_CHECK_INTERVAL: TimeDelta = TimeDelta::hours(1);
+static ACCESS_CHECK_RETRY_BACKOFF: TimeDelta = TimeDelta::minutes(5);
+static REFRESH_CLAIM_TIMEOUT: TimeDelta = TimeDelta::seconds(30);
+static REFRESH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+static REFRESH_POLL_ATTEMPTS: u32 = 25;
+
 pub struct ExtractSession(pub Session);
 
 impl<T, U> FromRequestParts<Arc<AppState<T, U>>> for ExtractSession
@@ -17,29 +43,964 @@ where
     T: Github + Send + Sync,
     U: Repository + Send + Sync,
 {
-    type Rejection = Redirect;
+    type Rejection = Response;
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &Arc<AppState<T, U>>,
     ) -> Result<Self, Self::Rejection> {
-        let cookies = CookieJar::from_request_parts(parts, state)
+        let login = {
+            let redirect = login_redirect(parts);
+            move || redirect.clone().into_response()
+        };
+        let cookies = SignedCookieJar::<CookieKey>::from_request_parts(parts, state)
             .await
             .expect("Failed to extract cookie");
-        let cookie = cookies
-            .get(SESSION_COOKIE)
-            .ok_or(Redirect::temporary("/login"))?;
-        let session_id =
-            Uuid::parse_str(cookie.value()).map_err(|_| Redirect::temporary("/login"))?;
+        let cookie = cookies.get(SESSION_COOKIE).ok_or_else(&login)?;
+        let session_id = Uuid::parse_str(cookie.value()).map_err(|_| login())?;
+        let session = load_session(session_id, state, login).await?;
+        touch_session(session_id, &session, parts, state).await;
+        Ok(ExtractSession(session))
+    }
+}
+
+pub struct ExtractApiSession(pub Session);
+
+impl<T, U> FromRequestParts<Arc<AppState<T, U>>> for ExtractApiSession
+where
+    T: Github + Send + Sync,
+    U: Repository + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<T, U>>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(api_token) = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        else {
+            let ExtractSession(session) = ExtractSession::from_request_parts(parts, state).await?;
+            return Ok(ExtractApiSession(session));
+        };
+        let unauthorized =
+            || AppError::Unauthorized("Invalid API token".to_string()).into_response();
+        let api_token = state
+            .repository
+            .get_api_token(&hash_api_token(api_token.trim()))
+            .await
+            .map_err(|e| AppError::from(e).into_response())?
+            .filter(|api_token| api_token.expires_at > Utc::now())
+            .ok_or_else(unauthorized)?;
+        let required_scope = match parts.method {
+            Method::GET | Method::HEAD => ApiScope::Read,
+            _ => ApiScope::Write,
+        };
+        if !api_token.scopes.contains(&required_scope) {
+            return Err(AppError::Forbidden(format!(
+                "API token lacks the {} scope",
+                required_scope.as_str()
+            ))
+            .into_response());
+        }
+        let session_id = Uuid::parse_str(&api_token.session_id).map_err(|_| unauthorized())?;
+        let session = load_session(session_id, state, unauthorized).await?;
+        touch_session(session_id, &session, parts, state).await;
+        Ok(ExtractApiSession(session))
+    }
+}
+
+pub trait MinimumRole {
+    const ROLE: Role;
+}
+
+pub struct ViewerRole;
+
+impl MinimumRole for ViewerRole {
+    const ROLE: Role = Role::Viewer;
+}
+
+pub struct TriagerRole;
+
+impl MinimumRole for TriagerRole {
+    const ROLE: Role = Role::Triager;
+}
+
+pub struct AdminRole;
+
+impl MinimumRole for AdminRole {
+    const ROLE: Role = Role::Admin;
+}
+
+pub struct ExtractRole<R: MinimumRole>(pub Session, pub PhantomData<R>);
+
+impl<T, U, R> FromRequestParts<Arc<AppState<T, U>>> for ExtractRole<R>
+where
+    T: Github + Send + Sync,
+    U: Repository + Send + Sync,
+    R: MinimumRole + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<T, U>>,
+    ) -> Result<Self, Self::Rejection> {
+        let ExtractApiSession(session) =
+            ExtractApiSession::from_request_parts(parts, state).await?;
+        let organization = RawPathParams::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|params| {
+                let param = |name| {
+                    params
+                        .iter()
+                        .find_map(|(key, value)| (key == name).then_some(value))
+                };
+                (param("owner_kind")? == "org").then(|| param("owner").map(str::to_string))?
+            });
+        let role = user_role(state, session.user_id, organization.as_deref())
+            .await
+            .map_err(|e| AppError::from(e).into_response())?;
+        if role < Some(R::ROLE) {
+            tracing::warn!(
+                "User {} lacks the {} role for {}",
+                session.user_id,
+                R::ROLE.as_str(),
+                parts.uri.path()
+            );
+            return Err(AppError::Forbidden(format!(
+                "You need the {} role to do this.",
+                R::ROLE.as_str()
+            ))
+            .into_response());
+        }
+        Ok(ExtractRole(session, PhantomData))
+    }
+}
+
+// Organization grants only apply to that organization's projects, global grants apply everywhere.
+pub async fn user_role<T: Github, U: Repository>(
+    state: &AppState<T, U>,
+    user_id: i32,
+    organization: Option<&str>,
+) -> Result<Option<Role>, RepositoryError> {
+    let role_grants = state.repository.get_user_roles(user_id).await?;
+    Ok(role_grants
+        .into_iter()
+        .filter(|role_grant| {
+            role_grant.organization.as_deref().is_none_or(|granted| {
+                organization.is_some_and(|organization| granted.eq_ignore_ascii_case(organization))
+            })
+        })
+        .map(|role_grant| role_grant.role)
+        .chain(state.config.default_role)
+        .max())
+}
+
+async fn load_session<T, U>(
+    session_id: Uuid,
+    state: &AppState<T, U>,
+    reject: impl Fn() -> Response,
+) -> Result<Session, Response>
+where
+    T: Github + Send + Sync,
+    U: Repository + Send + Sync,
+{
+    let session = state
+        .repository
+        .get_session(session_id)
+        .await
+        .map_err(|e| AppError::from(e).into_response())?
+        .ok_or_else(&reject)?;
+    let session = if session.expires_at <= Utc::now() {
+        refresh_session(session_id, &session, state)
+            .await
+            .map_err(|e| {
+                tracing::warn!(
+                    "Failed to refresh session of user {}: {}",
+                    session.user_id,
+                    e
+                );
+                reject()
+            })?
+    } else {
+        session
+    };
+    if Utc::now() - session.access_checked_at > ACCESS_CHECK_INTERVAL {
+        match revoke_if_not_allowed(session_id, &session, state).await {
+            Ok(Some(github_login)) => {
+                tracing::info!(
+                    "Revoked session of user {} who is no longer allowed",
+                    github_login
+                );
+                return Err(AppError::Forbidden(access_denied_message(
+                    &github_login,
+                    &state.config.allowed_groups,
+                ))
+                .into_response());
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to re-check access of user {}: {}",
+                    session.user_id,
+                    e
+                );
+                // Back off instead of repeating the membership calls on every request
+                let retry_at = Utc::now() - ACCESS_CHECK_INTERVAL + ACCESS_CHECK_RETRY_BACKOFF;
+                if let Err(e) = state
+                    .repository
+                    .mark_session_access_checked(session_id, retry_at)
+                    .await
+                {
+                    tracing::warn!(
+                        "Failed to back off access check of user {}: {}",
+                        session.user_id,
+                        e
+                    );
+                }
+            }
+        }
+    }
+    Ok(session)
+}
+
+async fn touch_session<T, U>(
+    session_id: Uuid,
+    session: &Session,
+    parts: &mut Parts,
+    state: &Arc<AppState<T, U>>,
+) where
+    T: Github + Send + Sync,
+    U: Repository + Send + Sync,
+{
+    if Utc::now() - session.last_used_at <= SESSION_TOUCH_INTERVAL {
+        return;
+    }
+    let ExtractClientInfo(client_info) = ExtractClientInfo::from_request_parts(parts, state)
+        .await
+        .expect("Failed to extract client info");
+    if let Err(e) = state
+        .repository
+        .touch_session(
+            session_id,
+            TouchSessionRequest {
+                last_used_at: Utc::now(),
+                user_agent: client_info.user_agent,
+                ip_address: client_info.ip_address,
+            },
+        )
+        .await
+    {
+        tracing::warn!("Failed to record session activity: {}", e);
+    }
+}
+
+fn login_redirect(parts: &Parts) -> Redirect {
+    let return_to = parts
+        .uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .filter(|_| parts.method == Method::GET);
+    match return_to {
+        Some(return_to) => {
+            let return_to: String = form_urlencoded::byte_serialize(return_to.as_bytes()).collect();
+            Redirect::temporary(&format!("/login?return_to={return_to}"))
+        }
+        None => Redirect::temporary("/login"),
+    }
+}
+
+// axum's own extractors, with rejections answered in the requested format like any AppError.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct ExtractPath<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct ExtractQuery<T>(pub T);
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Form), rejection(AppError))]
+pub struct ExtractForm<T>(pub T);
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct ExtractJson<T>(pub T);
+
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+pub struct ExtractClientInfo(pub ClientInfo);
+
+impl<T, U> FromRequestParts<Arc<AppState<T, U>>> for ExtractClientInfo
+where
+    T: Github + Send + Sync,
+    U: Repository + Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<T, U>>,
+    ) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(str::to_string);
+        let peer_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+        let trusted_proxies = &state.config.trusted_proxies;
+        // Proxies append to X-Forwarded-For, so the client is the rightmost hop that isn't a proxy.
+        let forwarded_for = peer_address
+            .filter(|peer_address| trusted_proxies.contains(peer_address))
+            .and_then(|_| parts.headers.get("X-Forwarded-For"))
+            .and_then(|forwarded_for| forwarded_for.to_str().ok())
+            .and_then(|forwarded_for| {
+                forwarded_for
+                    .rsplit(',')
+                    .map(|hop| hop.trim().parse::<IpAddr>())
+                    .find(|hop| !hop.as_ref().is_ok_and(|hop| trusted_proxies.contains(hop)))
+                    .and_then(Result::ok)
+            });
+        let ip_address = forwarded_for
+            .or(peer_address)
+            .map(|ip_address| ip_address.to_string());
+        Ok(ExtractClientInfo(ClientInfo {
+            user_agent,
+            ip_address,
+        }))
+    }
+}
+
+pub enum ResponseFormat {
+    Html,
+    Json,
+}
+
+pub struct ExtractFormat(pub ResponseFormat);
+
+impl<S: Send + Sync> FromRequestParts<S> for ExtractFormat {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accepts_html = parts
+            .headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"));
+        if accepts_html {
+            Ok(ExtractFormat(ResponseFormat::Html))
+        } else {
+            Ok(ExtractFormat(ResponseFormat::Json))
+        }
+    }
+}
+
+async fn refresh_session<T: Github, U: Repository>(
+    session_id: Uuid,
+    session: &Session,
+    state: &AppState<T, U>,
+) -> Result<Session, AppError> {
+    // Refresh tokens are single use, so concurrent requests wait for whichever one claimed it.
+    let claimed = state
+        .repository
+        .claim_session_refresh(
+            session_id,
+            session.expires_at,
+            Utc::now() - REFRESH_CLAIM_TIMEOUT,
+        )
+        .await?;
+    if !claimed {
+        return wait_for_refresh(session_id, state).await;
+    }
+    let res = state
+        .github
+        .post_login_oauth_refresh_token(
+            CLIENT_ID,
+            &session.refresh_token,
+            &state.secrets.client_secret,
+        )
+        .await?;
+    let expires_at = res.expires_at();
+    let refresh_expires_at = res.refresh_expires_at();
+    let session = state
+        .repository
+        .update_session_tokens(
+            session_id,
+            UpdateSessionTokensRequest {
+                access_token: res.access_token,
+                refresh_token: res.refresh_token,
+                expires_at,
+                refresh_expires_at,
+            },
+        )
+        .await?;
+    tracing::info!("Refreshed access token for user {}", session.user_id);
+    Ok(session)
+}
+
+async fn wait_for_refresh<T: Github, U: Repository>(
+    session_id: Uuid,
+    state: &AppState<T, U>,
+) -> Result<Session, AppError> {
+    for _ in 0..REFRESH_POLL_ATTEMPTS {
+        tokio::time::sleep(REFRESH_POLL_INTERVAL).await;
         let session = state
             .repository
             .get_session(session_id)
-            .await
-            .map_err(|_| Redirect::temporary("/login"))?
-            .ok_or(Redirect::temporary("/login"))?;
+            .await?
+            .ok_or_else(|| AppError::Internal(format!("Session {session_id} was deleted")))?;
         if session.expires_at > Utc::now() {
-            return Err(Redirect::temporary("/login"));
+            return Ok(session);
         }
-        Ok(ExtractSession(session))
+    }
+    Err(AppError::Internal(format!(
+        "Session {session_id} was not refreshed in time"
+    )))
+}
+
+async fn revoke_if_not_allowed<T: Github + Sync, U: Repository>(
+    session_id: Uuid,
+    session: &Session,
+    state: &AppState<T, U>,
+) -> Result<Option<String>, AppError> {
+    let user = state
+        .repository
+        .get_user(session.user_id)
+        .await?
+        .ok_or_else(|| AppError::Internal(format!("User {} not found", session.user_id)))?;
+    if state
+        .github
+        .is_allowed(
+            &state.config.allowed_groups,
+            &user.github_login,
+            &session.access_token,
+        )
+        .await?
+    {
+        state
+            .repository
+            .mark_session_access_checked(session_id, Utc::now())
+            .await?;
+        return Ok(None);
+    }
+    state.repository.delete_session(session_id).await?;
+    Ok(Some(user.github_login))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use axum::{
+        body::to_bytes,
+        extract::{ConnectInfo, FromRequestParts},
+        http::{
+            Method, Request, StatusCode,
+            header::{AUTHORIZATION, COOKIE, LOCATION},
+        },
+    };
+    use chrono::{TimeDelta, Utc};
+    use mockall::predicate::eq;
+    use serde_json::{Value, json};
+    use sqlx::types::Uuid;
+
+    use crate::{
+        app::AppConfig,
+        crypto::hash_api_token,
+        extractors::{
+            ACCESS_CHECK_INTERVAL, ACCESS_CHECK_RETRY_BACKOFF, ExtractApiSession,
+            ExtractClientInfo, ExtractRole, ExtractSession, TriagerRole, user_role,
+        },
+        fixtures::{TestState, session_cookie, test_session, test_state},
+        model::{AllowedGroup, ApiScope, ApiToken, Role, RoleGrant, Session, User},
+        repository::{MockRepository, RepositoryError},
+    };
+
+    #[tokio::test]
+    async fn expired_session_is_refreshed_and_persisted() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+
+        repository_mock
+            .expect_get_session()
+            .with(eq(session_id))
+            .times(1)
+            .returning(move |id| {
+                Ok(Some(Session {
+                    expires_at: Utc::now() - TimeDelta::minutes(1),
+                    ..test_session(id)
+                }))
+            });
+        repository_mock
+            .expect_claim_session_refresh()
+            .withf(move |id, _, _| *id == session_id)
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+        repository_mock
+            .expect_update_session_tokens()
+            .withf(move |id, req| {
+                *id == session_id
+                    && req.access_token == "refreshed_refresh_token"
+                    && req.refresh_token == "new_refresh_token"
+                    && req.expires_at > Utc::now()
+            })
+            .times(1)
+            .returning(|id, req| {
+                Ok(Session {
+                    id: id.to_string(),
+                    user_id: 1,
+                    access_token: req.access_token,
+                    refresh_token: req.refresh_token,
+                    expires_at: req.expires_at,
+                    refresh_expires_at: req.refresh_expires_at,
+                    last_used_at: Utc::now(),
+                    access_checked_at: Utc::now(),
+                })
+            });
+
+        let state = test_state(AppConfig::default(), repository_mock);
+        let session_cookie = session_cookie(&state, session_id);
+        let (mut parts, _) = Request::builder()
+            .header(COOKIE, session_cookie)
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let ExtractSession(session) = ExtractSession::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(session.access_token, "refreshed_refresh_token");
+    }
+
+    #[tokio::test]
+    async fn concurrent_refresh_waits_for_the_claiming_request() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+        let reads = AtomicUsize::new(0);
+
+        repository_mock
+            .expect_get_session()
+            .with(eq(session_id))
+            .times(2)
+            .returning(move |id| {
+                let refreshed = reads.fetch_add(1, Ordering::SeqCst) > 0;
+                Ok(Some(Session {
+                    access_token: if refreshed {
+                        "refreshed_elsewhere".to_string()
+                    } else {
+                        "access_token".to_string()
+                    },
+                    expires_at: if refreshed {
+                        Utc::now() + TimeDelta::hours(8)
+                    } else {
+                        Utc::now() - TimeDelta::minutes(1)
+                    },
+                    ..test_session(id)
+                }))
+            });
+        repository_mock
+            .expect_claim_session_refresh()
+            .times(1)
+            .returning(|_, _, _| Ok(false));
+        repository_mock.expect_update_session_tokens().times(0);
+
+        let state = test_state(AppConfig::default(), repository_mock);
+        let session_cookie = session_cookie(&state, session_id);
+        let (mut parts, _) = Request::builder()
+            .header(COOKIE, session_cookie)
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let ExtractSession(session) = ExtractSession::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(session.access_token, "refreshed_elsewhere");
+    }
+
+    #[tokio::test]
+    async fn missing_session_redirects_to_login_with_return_to() {
+        let state = test_state(AppConfig::default(), MockRepository::new());
+        let (mut parts, _) = Request::builder()
+            .uri("/org/acme/project/7?field=Status")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let rejection = ExtractSession::from_request_parts(&mut parts, &state)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            rejection.headers().get(LOCATION).unwrap(),
+            "/login?return_to=%2Forg%2Facme%2Fproject%2F7%3Ffield%3DStatus"
+        );
+    }
+
+    #[tokio::test]
+    async fn session_is_revoked_when_user_leaves_allowed_groups() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+
+        repository_mock
+            .expect_get_session()
+            .with(eq(session_id))
+            .returning(|id| {
+                Ok(Some(Session {
+                    access_checked_at: Utc::now() - TimeDelta::hours(2),
+                    ..test_session(id)
+                }))
+            });
+        repository_mock
+            .expect_get_user()
+            .with(eq(1))
+            .returning(|id| {
+                Ok(Some(User {
+                    id,
+                    github_login: "user_login".to_string(),
+                }))
+            });
+        repository_mock
+            .expect_delete_session()
+            .with(eq(session_id))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let state = test_state(
+            AppConfig {
+                allowed_groups: vec![AllowedGroup::Organization("other_org".to_string())],
+                ..AppConfig::default()
+            },
+            repository_mock,
+        );
+        let session_cookie = session_cookie(&state, session_id);
+        let (mut parts, _) = Request::builder()
+            .header(COOKIE, session_cookie)
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let rejection = ExtractSession::from_request_parts(&mut parts, &state)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(rejection.status(), StatusCode::FORBIDDEN);
+        let body = to_bytes(rejection.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({
+                "error": "The GitHub account user_login is no longer allowed to use Argonath, \
+                    access is limited to members of other_org."
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_access_check_is_retried_after_a_backoff() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+
+        repository_mock
+            .expect_get_session()
+            .with(eq(session_id))
+            .returning(|id| {
+                Ok(Some(Session {
+                    access_checked_at: Utc::now() - TimeDelta::hours(2),
+                    ..test_session(id)
+                }))
+            });
+        repository_mock
+            .expect_get_user()
+            .with(eq(1))
+            .returning(|id| {
+                Ok(Some(User {
+                    id,
+                    github_login: "user_login".to_string(),
+                }))
+            });
+        repository_mock.expect_delete_session().never();
+        repository_mock
+            .expect_mark_session_access_checked()
+            .withf(move |id, checked_at| {
+                let retry_in = *checked_at + ACCESS_CHECK_INTERVAL - Utc::now();
+                *id == session_id
+                    && retry_in > TimeDelta::zero()
+                    && retry_in <= ACCESS_CHECK_RETRY_BACKOFF
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let state = test_state(
+            AppConfig {
+                allowed_groups: vec![AllowedGroup::Organization("unreadable_org".to_string())],
+                ..AppConfig::default()
+            },
+            repository_mock,
+        );
+        let session_cookie = session_cookie(&state, session_id);
+        let (mut parts, _) = Request::builder()
+            .header(COOKIE, session_cookie)
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let ExtractSession(session) = ExtractSession::from_request_parts(&mut parts, &state)
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(session.id, session_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn forwarded_for_is_only_honoured_from_trusted_proxies() {
+        let state = test_state(
+            AppConfig {
+                trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+                ..AppConfig::default()
+            },
+            MockRepository::new(),
+        );
+        let client_ip = async |peer: &str| {
+            let (mut parts, _) = Request::builder()
+                .header("X-Forwarded-For", "6.6.6.6, 203.0.113.7, 10.0.0.1")
+                .extension(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 443)))
+                .body(())
+                .unwrap()
+                .into_parts();
+            let ExtractClientInfo(client_info) =
+                ExtractClientInfo::from_request_parts(&mut parts, &state)
+                    .await
+                    .unwrap();
+            client_info.ip_address.unwrap()
+        };
+
+        assert_eq!(client_ip("10.0.0.1").await, "203.0.113.7");
+        assert_eq!(client_ip("198.51.100.2").await, "198.51.100.2");
+    }
+
+    fn api_token(scopes: Vec<ApiScope>) -> ApiToken {
+        ApiToken {
+            id: Uuid::from_u128(2).to_string(),
+            user_id: 1,
+            session_id: Uuid::from_u128(1).to_string(),
+            name: "ci".to_string(),
+            scopes,
+            expires_at: Utc::now() + TimeDelta::days(1),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn api_token_state(scopes: Vec<ApiScope>, config: AppConfig) -> Arc<TestState> {
+        let mut repository_mock = MockRepository::new();
+
+        repository_mock
+            .expect_get_api_token()
+            .with(eq(hash_api_token("api_token")))
+            .returning(move |_| Ok(Some(api_token(scopes.clone()))));
+        repository_mock
+            .expect_get_session()
+            .with(eq(Uuid::from_u128(1)))
+            .returning(|id| Ok(Some(test_session(id))));
+        repository_mock
+            .expect_get_user_roles()
+            .returning(|_| Ok(vec![]));
+        test_state(config, repository_mock)
+    }
+
+    #[tokio::test]
+    async fn bearer_api_token_resolves_to_its_session() {
+        let state = api_token_state(vec![ApiScope::Read], AppConfig::default());
+        let (mut parts, _) = Request::builder()
+            .header(AUTHORIZATION, "Bearer api_token")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let ExtractApiSession(session) = ExtractApiSession::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(session.access_token, "access_token");
+    }
+
+    #[tokio::test]
+    async fn bearer_api_token_needs_write_scope_to_mutate() {
+        let state = api_token_state(vec![ApiScope::Read], AppConfig::default());
+        let (mut parts, _) = Request::builder()
+            .method(Method::POST)
+            .header(AUTHORIZATION, "Bearer api_token")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let rejection = ExtractApiSession::from_request_parts(&mut parts, &state)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(rejection.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn api_token_lookup_failures_are_server_errors() {
+        let bearer_status = async |repository_mock| {
+            let state = test_state(AppConfig::default(), repository_mock);
+            let (mut parts, _) = Request::builder()
+                .header(AUTHORIZATION, "Bearer api_token")
+                .body(())
+                .unwrap()
+                .into_parts();
+            ExtractApiSession::from_request_parts(&mut parts, &state)
+                .await
+                .err()
+                .unwrap()
+                .status()
+        };
+
+        let mut token_lookup_fails = MockRepository::new();
+        token_lookup_fails
+            .expect_get_api_token()
+            .returning(|_| Err(RepositoryError::FailedToGetApiTokensError));
+        assert_eq!(
+            bearer_status(token_lookup_fails).await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let mut session_lookup_fails = MockRepository::new();
+        session_lookup_fails
+            .expect_get_api_token()
+            .returning(|_| Ok(Some(api_token(vec![ApiScope::Read]))));
+        session_lookup_fails
+            .expect_get_session()
+            .returning(|_| Err(RepositoryError::FailedToDecryptTokensError));
+        assert_eq!(
+            bearer_status(session_lookup_fails).await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let mut session_is_gone = MockRepository::new();
+        session_is_gone
+            .expect_get_api_token()
+            .returning(|_| Ok(Some(api_token(vec![ApiScope::Read]))));
+        session_is_gone.expect_get_session().returning(|_| Ok(None));
+        assert_eq!(
+            bearer_status(session_is_gone).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn bearer_api_token_records_session_activity() {
+        let session_id = Uuid::from_u128(1);
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_get_api_token()
+            .returning(|_| Ok(Some(api_token(vec![ApiScope::Read]))));
+        repository_mock.expect_get_session().returning(|id| {
+            Ok(Some(Session {
+                last_used_at: Utc::now() - TimeDelta::hours(1),
+                ..test_session(id)
+            }))
+        });
+        repository_mock
+            .expect_touch_session()
+            .withf(move |id, req| {
+                *id == session_id && req.last_used_at > Utc::now() - TimeDelta::minutes(1)
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let state = test_state(AppConfig::default(), repository_mock);
+        let (mut parts, _) = Request::builder()
+            .header(AUTHORIZATION, "Bearer api_token")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        ExtractApiSession::from_request_parts(&mut parts, &state)
+            .await
+            .ok()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn viewers_are_forbidden_from_triager_routes() {
+        let state = api_token_state(vec![ApiScope::Read, ApiScope::Write], AppConfig::default());
+        let (mut parts, _) = Request::builder()
+            .method(Method::POST)
+            .header(AUTHORIZATION, "Bearer api_token")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let rejection = ExtractRole::<TriagerRole>::from_request_parts(&mut parts, &state)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(rejection.status(), StatusCode::FORBIDDEN);
+        let body = to_bytes(rejection.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "You need the triager role to do this." })
+        );
+    }
+
+    #[tokio::test]
+    async fn organization_roles_only_apply_to_that_organization() {
+        let mut repository_mock = MockRepository::new();
+
+        repository_mock
+            .expect_get_user_roles()
+            .with(eq(1))
+            .returning(|user_id| {
+                Ok(vec![RoleGrant {
+                    user_id,
+                    github_login: "test_user".to_string(),
+                    organization: Some("acme".to_string()),
+                    role: Role::Triager,
+                }])
+            });
+        let state = test_state(
+            AppConfig {
+                default_role: Some(Role::Viewer),
+                ..AppConfig::default()
+            },
+            repository_mock,
+        );
+
+        assert_eq!(
+            user_role(&state, 1, Some("Acme")).await.unwrap(),
+            Some(Role::Triager)
+        );
+        assert_eq!(
+            user_role(&state, 1, Some("other")).await.unwrap(),
+            Some(Role::Viewer)
+        );
+        assert_eq!(
+            user_role(&state, 1, None).await.unwrap(),
+            Some(Role::Viewer)
+        );
     }
 }
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{
+        Request,
+        header::{COOKIE, SET_COOKIE},
+    },
+    response::IntoResponse,
+};
+use axum_extra::extract::{
+    SignedCookieJar,
+    cookie::{Cookie, Key},
+};
+use chrono::{TimeDelta, Utc};
+use sqlx::types::Uuid;
+
+use crate::{
+    AppSecrets,
+    app::{AppConfig, AppState, CookieKey},
+    controller::SESSION_COOKIE,
+    github::mock::MockGithubService,
+    model::Session,
+    repository::MockRepository,
+};
+
+pub type TestState = AppState<MockGithubService, MockRepository>;
+
+pub fn test_state(config: AppConfig, repository: MockRepository) -> Arc<TestState> {
+    let secrets = Box::leak(Box::new(AppSecrets {
+        client_secret: "client_secret".to_string(),
+        pg_url: "test_url".to_string(),
+        cookie_key: Vec::new(),
+        token_keys: String::new(),
+        github_app_private_key: String::new(),
+    }));
+    Arc::new(AppState {
+        secrets,
+        cookie_key: Key::generate(),
+        config,
+        github: MockGithubService,
+        repository,
+    })
+}
+
+// A fresh session of user 1, tests override the timestamps they care about.
+pub fn test_session(session_id: Uuid) -> Session {
+    Session {
+        id: session_id.to_string(),
+        user_id: 1,
+        access_token: "access_token".to_string(),
+        refresh_token: "refresh_token".to_string(),
+        expires_at: Utc::now() + TimeDelta::hours(1),
+        refresh_expires_at: Utc::now() + TimeDelta::days(180),
+        last_used_at: Utc::now(),
+        access_checked_at: Utc::now(),
+    }
+}
+
+// The Cookie header a browser sends back for a cookie signed with the state's key.
+pub fn signed_cookie(state: &TestState, name: &'static str, value: String) -> String {
+    let response = SignedCookieJar::new(state.cookie_key.clone())
+        .add(Cookie::new(name, value))
+        .into_response();
+    let set_cookie = response
+        .headers()
+        .get(SET_COOKIE)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+pub fn session_cookie(state: &TestState, session_id: Uuid) -> String {
+    signed_cookie(state, SESSION_COOKIE, session_id.to_string())
+}
+
+pub async fn signed_jar(
+    state: &Arc<TestState>,
+    name: &'static str,
+    value: String,
+) -> SignedCookieJar<CookieKey> {
+    let (mut parts, _) = Request::builder()
+        .header(COOKIE, signed_cookie(state, name, value))
+        .body(())
+        .unwrap()
+        .into_parts();
+    SignedCookieJar::from_request_parts(&mut parts, state)
+        .await
+        .unwrap()
+}
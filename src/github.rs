@@ -1,22 +1,63 @@
-use graphql_client::{GraphQLQuery, Response};
-use reqwest::header;
-use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{NaiveDate, TimeDelta};
+use futures::{StreamExt, stream};
+use graphql_client::GraphQLQuery;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::json;
 use sqlx::types::chrono::{DateTime as ChronoDateTime, Utc};
 
-use crate::controller::AppError;
+use crate::{
+    controller::AppError,
+    model::{AllowedGroup, FieldType, RequiredField},
+};
 
-static GITHUB_GRAPHQL_URL: &str = "https://api.github.com/graphql";
+static GITHUB_API_URL: &str = "https://api.github.com";
+static GITHUB_OAUTH_URL: &str = "https://github.com/login/oauth/access_token";
+static MAX_CONCURRENT_MUTATIONS: usize = 5;
+static APP_JWT_LIFETIME: TimeDelta = TimeDelta::minutes(9);
+static INSTALLATION_TOKEN_EXPIRY_MARGIN: TimeDelta = TimeDelta::minutes(5);
+static DEFAULT_REFRESH_TOKEN_LIFETIME: TimeDelta = TimeDelta::days(182);
+pub static DEFAULT_REQUIRED_FIELD: &str = "Task type";
 
 pub struct GithubService {
     pub client: reqwest::Client,
+    api_url: String,
+    installation_tokens: Mutex<HashMap<String, InstallationTokenSlot>>,
 }
 
+// Locked across the token exchange so concurrent misses for an organization share one fetch.
+type InstallationTokenSlot = Arc<tokio::sync::Mutex<Option<InstallationToken>>>;
+
 #[derive(Deserialize, Debug)]
 pub struct OauthResponse {
     pub access_token: String,
     pub refresh_token: String,
     pub expires_in: i64,
+    pub refresh_token_expires_in: Option<i64>,
+}
+
+impl OauthResponse {
+    pub fn expires_at(&self) -> DateTime {
+        Utc::now()
+            .checked_add_signed(TimeDelta::seconds(self.expires_in))
+            .expect("Failed to add time")
+    }
+
+    pub fn refresh_expires_at(&self) -> DateTime {
+        let lifetime = self
+            .refresh_token_expires_in
+            .map_or(DEFAULT_REFRESH_TOKEN_LIFETIME, TimeDelta::seconds);
+        Utc::now()
+            .checked_add_signed(lifetime)
+            .expect("Failed to add time")
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -24,18 +65,193 @@ pub struct UserResponse {
     pub login: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct TeamMembershipResponse {
+    state: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstallationResponse {
+    id: i64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct InstallationToken {
+    token: String,
+    expires_at: DateTime,
+}
+
+#[derive(Serialize)]
+struct AppClaims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum OwnerKind {
+    #[serde(rename = "org")]
+    Organization,
+    #[serde(rename = "user")]
+    User,
+}
+
+impl OwnerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OwnerKind::Organization => "org",
+            OwnerKind::User => "user",
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
-pub struct Item {
+pub struct ProjectSummary {
+    pub number: i64,
     pub title: String,
-    pub author: String,
-    pub url: String,
+    pub closed: bool,
+    pub item_count: i64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ProjectList {
+    pub projects: Vec<ProjectSummary>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnmappedItems {
+    pub project_id: String,
+    pub fields: Vec<ProjectField>,
+    pub items: Vec<Item>,
+    pub warnings: Vec<String>,
+}
+
+// graphql_client's error type drops the `type` GitHub uses to tell NOT_FOUND from FORBIDDEN.
 #[derive(Deserialize, Debug)]
-pub struct ProjectsResponse {}
+pub struct GraphqlResponse<R> {
+    pub data: Option<R>,
+    #[serde(default)]
+    pub errors: Vec<GraphqlError>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GraphqlError {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub error_type: Option<String>,
+    #[serde(default)]
+    pub path: Vec<serde_json::Value>,
+}
+
+impl Display for GraphqlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            return write!(f, "{}", self.message);
+        }
+        let path = self
+            .path
+            .iter()
+            .map(|segment| match segment {
+                serde_json::Value::String(segment) => segment.clone(),
+                segment => segment.to_string(),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{} (at {})", self.message, path.join("."))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectField {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    pub options: Vec<FieldOption>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iteration_configuration: Option<IterationConfiguration>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldOption {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IterationConfiguration {
+    pub duration: i64,
+    pub start_day: i64,
+    pub iterations: Vec<Iteration>,
+    pub completed_iterations: Vec<Iteration>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Iteration {
+    pub id: String,
+    pub title: String,
+    pub start_date: Date,
+    pub duration: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldValue {
+    SingleSelectOptionId(String),
+    IterationId(String),
+    Number(f64),
+    Date(NaiveDate),
+    Text(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkUpdateReport {
+    pub dry_run: bool,
+    pub results: Vec<ItemUpdateResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemUpdateResult {
+    pub item_id: String,
+    pub status: ItemUpdateStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemUpdateStatus {
+    Updated,
+    WouldUpdate,
+    AlreadySet,
+    Unknown,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Item {
+    pub id: String,
+    pub kind: ItemKind,
+    pub number: Option<i64>,
+    pub title: String,
+    pub author: Option<String>,
+    pub avatar_url: Option<String>,
+    pub url: Option<String>,
+    pub created_at: DateTime,
+    pub missing_fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Issue,
+    PullRequest,
+    DraftIssue,
+}
 
 pub type DateTime = ChronoDateTime<Utc>;
 
+pub type Date = NaiveDate;
+
+#[allow(clippy::upper_case_acronyms)]
 pub type URI = String;
 
 #[derive(GraphQLQuery)]
@@ -46,21 +262,207 @@ pub type URI = String;
 )]
 pub struct ProjectsQuery;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.docs.graphql",
+    query_path = "graphql/project_id_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct ProjectIdQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.docs.graphql",
+    query_path = "graphql/project_fields_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct ProjectFieldsQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.docs.graphql",
+    query_path = "graphql/projects_list_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct ProjectsListQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.docs.graphql",
+    query_path = "graphql/item_field_values_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct ItemFieldValuesQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.docs.graphql",
+    query_path = "graphql/update_item_field_mutation.graphql",
+    response_derives = "Debug",
+    skip_serializing_none
+)]
+pub struct UpdateItemFieldMutation;
+
 pub trait Github {
     fn new() -> Self;
-    async fn post_login_oauth_access_token(
+    fn post_login_oauth_access_token(
         &self,
         client_id: &str,
         code: &str,
+        code_verifier: &str,
         client_secret: &str,
-    ) -> Result<OauthResponse, AppError>;
-    async fn get_user(&self, access_token: &str) -> Result<UserResponse, AppError>;
-    async fn get_unmapped_items(
+    ) -> impl Future<Output = Result<OauthResponse, AppError>> + Send;
+    fn post_login_oauth_refresh_token(
+        &self,
+        client_id: &str,
+        refresh_token: &str,
+        client_secret: &str,
+    ) -> impl Future<Output = Result<OauthResponse, AppError>> + Send;
+    fn delete_app_grant(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        access_token: &str,
+    ) -> impl Future<Output = Result<(), AppError>> + Send;
+    fn get_user(
+        &self,
+        access_token: &str,
+    ) -> impl Future<Output = Result<UserResponse, AppError>> + Send;
+    fn get_installation_token(
+        &self,
+        client_id: &str,
+        private_key: &str,
+        organization: &str,
+    ) -> impl Future<Output = Result<String, AppError>> + Send;
+    fn is_organization_member(
+        &self,
+        organization: &str,
+        login: &str,
+        access_token: &str,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+    fn is_team_member(
+        &self,
+        organization: &str,
+        team: &str,
+        login: &str,
+        access_token: &str,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+    fn list_projects(
+        &self,
+        owner_kind: OwnerKind,
+        owner: String,
+        access_token: &str,
+    ) -> impl Future<Output = Result<ProjectList, AppError>> + Send;
+    fn get_unmapped_items(
+        &self,
+        owner_kind: OwnerKind,
+        owner: String,
+        id: u32,
+        required_fields: &[RequiredField],
+        access_token: &str,
+    ) -> impl Future<Output = Result<UnmappedItems, AppError>> + Send;
+    fn get_project_id(
+        &self,
+        owner_kind: OwnerKind,
+        owner: String,
+        id: u32,
+        access_token: &str,
+    ) -> impl Future<Output = Result<String, AppError>> + Send;
+    fn get_project_fields(
         &self,
-        org: String,
+        owner_kind: OwnerKind,
+        owner: String,
         id: u32,
         access_token: &str,
-    ) -> Result<Vec<Item>, AppError>;
+    ) -> impl Future<Output = Result<Vec<ProjectField>, AppError>> + Send;
+    // Items that are not in the project are left out, items without a value map to None.
+    fn get_item_field_values(
+        &self,
+        project_id: &str,
+        item_ids: &[String],
+        field_id: &str,
+        access_token: &str,
+    ) -> impl Future<Output = Result<HashMap<String, Option<FieldValue>>, AppError>> + Send;
+    fn update_item_field(
+        &self,
+        project_id: &str,
+        item_id: &str,
+        field_id: &str,
+        value: FieldValue,
+        access_token: &str,
+    ) -> impl Future<Output = Result<(), AppError>> + Send;
+    fn update_items_field(
+        &self,
+        project_id: &str,
+        item_ids: Vec<String>,
+        field_id: &str,
+        value: FieldValue,
+        access_token: &str,
+    ) -> impl Future<Output = Vec<ItemUpdateResult>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            stream::iter(item_ids)
+                .map(|item_id| {
+                    let value = value.clone();
+                    async move {
+                        let status = match self
+                            .update_item_field(project_id, &item_id, field_id, value, access_token)
+                            .await
+                        {
+                            Ok(()) => ItemUpdateStatus::Updated,
+                            Err(e) => {
+                                tracing::warn!("Failed to update item {}: {}", item_id, e);
+                                ItemUpdateStatus::Failed
+                            }
+                        };
+                        ItemUpdateResult { item_id, status }
+                    }
+                })
+                .buffered(MAX_CONCURRENT_MUTATIONS)
+                .collect()
+                .await
+        }
+    }
+    fn is_allowed(
+        &self,
+        allowed_groups: &[AllowedGroup],
+        login: &str,
+        access_token: &str,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            if allowed_groups.is_empty() {
+                return Ok(true);
+            }
+            // A group that can't be checked doesn't hide a later group the user belongs to.
+            let mut failed_check = None;
+            for allowed_group in allowed_groups {
+                let is_member = match allowed_group {
+                    AllowedGroup::Organization(organization) => {
+                        self.is_organization_member(organization, login, access_token)
+                            .await
+                    }
+                    AllowedGroup::Team { organization, team } => {
+                        self.is_team_member(organization, team, login, access_token)
+                            .await
+                    }
+                };
+                match is_member {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {}
+                    Err(e) => {
+                        tracing::warn!("Failed to check membership of {}: {}", allowed_group, e);
+                        failed_check = Some(e);
+                    }
+                }
+            }
+            failed_check.map_or(Ok(false), Err)
+        }
+    }
 }
 
 impl Github for GithubService {
@@ -78,98 +480,1358 @@ impl Github for GithubService {
             .default_headers(headers)
             .build()
             .expect("Failed to build client");
-        GithubService { client }
+        GithubService {
+            client,
+            api_url: GITHUB_API_URL.to_string(),
+            installation_tokens: Mutex::new(HashMap::new()),
+        }
     }
     async fn post_login_oauth_access_token(
         &self,
         client_id: &str,
         code: &str,
+        code_verifier: &str,
+        client_secret: &str,
+    ) -> Result<OauthResponse, AppError> {
+        self.client
+            .post(GITHUB_OAUTH_URL)
+            .json(&json!({
+                "client_id": client_id,
+                "code": code,
+                "code_verifier": code_verifier,
+                "client_secret": client_secret
+            }))
+            .send()
+            .await
+            .map_err(AppError::from)
+            .and_then(check_status)?
+            .json::<OauthResponse>()
+            .await
+            .map_err(AppError::from)
+    }
+
+    async fn post_login_oauth_refresh_token(
+        &self,
+        client_id: &str,
+        refresh_token: &str,
         client_secret: &str,
     ) -> Result<OauthResponse, AppError> {
         self.client
-            .post("https://github.com/login/oauth/access_token")
-            .json(&json!({ "client_id": client_id, "code": code, "client_secret": client_secret }))
+            .post(GITHUB_OAUTH_URL)
+            .json(&json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+            }))
             .send()
             .await
-            .map_err(|_| AppError)?
+            .map_err(AppError::from)
+            .and_then(check_status)?
             .json::<OauthResponse>()
             .await
-            .map_err(|_| AppError)
+            .map_err(AppError::from)
+    }
+
+    async fn delete_app_grant(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        access_token: &str,
+    ) -> Result<(), AppError> {
+        self.client
+            .delete(format!("{}/applications/{client_id}/grant", self.api_url))
+            .basic_auth(client_id, Some(client_secret))
+            .json(&json!({ "access_token": access_token }))
+            .send()
+            .await
+            .map_err(AppError::from)
+            .and_then(check_status)?;
+        Ok(())
     }
 
     async fn get_user(&self, access_token: &str) -> Result<UserResponse, AppError> {
         self.client
-            .get("https://api.github.com/user")
+            .get(format!("{}/user", self.api_url))
             .header("Authorization", format!("Bearer {access_token}"))
             .send()
             .await
-            .map_err(|_| AppError)?
+            .map_err(AppError::from)
+            .and_then(check_status)?
             .json::<UserResponse>()
             .await
-            .map_err(|_| AppError)
+            .map_err(AppError::from)
+    }
+
+    async fn get_installation_token(
+        &self,
+        client_id: &str,
+        private_key: &str,
+        organization: &str,
+    ) -> Result<String, AppError> {
+        let slot = self.installation_token_slot(organization);
+        let mut cached_token = slot.lock().await;
+        if let Some(installation_token) = cached_token.as_ref().filter(|installation_token| {
+            installation_token.expires_at - INSTALLATION_TOKEN_EXPIRY_MARGIN > Utc::now()
+        }) {
+            return Ok(installation_token.token.clone());
+        }
+        let jwt = app_jwt(client_id, private_key)?;
+        let installation = self
+            .client
+            .get(format!("{}/orgs/{organization}/installation", self.api_url))
+            .header("Authorization", format!("Bearer {jwt}"))
+            .send()
+            .await
+            .map_err(AppError::from)
+            .and_then(check_status)?
+            .json::<InstallationResponse>()
+            .await?;
+        let installation_token = self
+            .client
+            .post(format!(
+                "{}/app/installations/{}/access_tokens",
+                self.api_url, installation.id
+            ))
+            .header("Authorization", format!("Bearer {jwt}"))
+            .send()
+            .await
+            .map_err(AppError::from)
+            .and_then(check_status)?
+            .json::<InstallationToken>()
+            .await?;
+        tracing::info!(
+            "Created installation token for {} valid until {}",
+            organization,
+            installation_token.expires_at
+        );
+        let token = installation_token.token.clone();
+        *cached_token = Some(installation_token);
+        Ok(token)
+    }
+
+    async fn is_organization_member(
+        &self,
+        organization: &str,
+        login: &str,
+        access_token: &str,
+    ) -> Result<bool, AppError> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/orgs/{organization}/members/{login}",
+                self.api_url
+            ))
+            .header("Authorization", format!("Bearer {access_token}"))
+            .send()
+            .await?;
+        match check_status(response) {
+            Ok(response) if response.status() == StatusCode::NO_CONTENT => Ok(true),
+            Ok(response) => Err(AppError::GithubStatus(response.status())),
+            Err(AppError::GithubStatus(StatusCode::NOT_FOUND)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn is_team_member(
+        &self,
+        organization: &str,
+        team: &str,
+        login: &str,
+        access_token: &str,
+    ) -> Result<bool, AppError> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/orgs/{organization}/teams/{team}/memberships/{login}",
+                self.api_url
+            ))
+            .header("Authorization", format!("Bearer {access_token}"))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        let membership = check_status(response)?
+            .json::<TeamMembershipResponse>()
+            .await?;
+        Ok(membership.state == "active")
+    }
+
+    async fn list_projects(
+        &self,
+        owner_kind: OwnerKind,
+        owner: String,
+        access_token: &str,
+    ) -> Result<ProjectList, AppError> {
+        let mut has_next_page = true;
+        let mut project_list = ProjectList {
+            projects: vec![],
+            warnings: vec![],
+        };
+        static FETCH_AMOUNT: i64 = 100;
+        let mut end_cursor = None;
+        while has_next_page {
+            let request_body = ProjectsListQuery::build_query(projects_list_query::Variables {
+                login: owner.clone(),
+                is_organization: owner_kind == OwnerKind::Organization,
+                first: FETCH_AMOUNT,
+                after: end_cursor.clone(),
+            });
+            let GraphqlResponse { data, errors }: GraphqlResponse<
+                projects_list_query::ResponseData,
+            > = self.post_graphql(&request_body, access_token).await?;
+
+            let Some(connection) = data.and_then(|data| match owner_kind {
+                OwnerKind::Organization => data
+                    .organization
+                    .map(|organization| organization.projects_v2),
+                OwnerKind::User => data.user.map(|user| user.projects_v2),
+            }) else {
+                return Err(missing_owner(errors, owner));
+            };
+            project_list.warnings.extend(partial_data_warnings(&errors));
+            end_cursor = connection.page_info.end_cursor;
+            has_next_page = connection.page_info.has_next_page;
+            project_list
+                .projects
+                .extend(
+                    connection
+                        .nodes
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .map(|project| ProjectSummary {
+                            number: project.number,
+                            title: project.title,
+                            closed: project.closed,
+                            item_count: project.items.total_count,
+                        }),
+                );
+        }
+        Ok(project_list)
     }
 
     async fn get_unmapped_items(
         &self,
-        org: String,
+        owner_kind: OwnerKind,
+        owner: String,
         id: u32,
+        required_fields: &[RequiredField],
         access_token: &str,
-    ) -> Result<Vec<Item>, AppError> {
+    ) -> Result<UnmappedItems, AppError> {
+        let fields = self
+            .get_project_fields(owner_kind, owner.clone(), id, access_token)
+            .await?
+            .into_iter()
+            .filter(|field| {
+                required_fields.iter().any(|required| {
+                    required.name == field.name && required.field_type == field.field_type
+                })
+            })
+            .collect();
         let mut has_next_page = true;
-        let mut unmapped_items = vec![];
+        let mut unmapped_items = UnmappedItems {
+            project_id: String::new(),
+            fields,
+            items: vec![],
+            warnings: vec![],
+        };
         static FETCH_AMOUNT: i64 = 100;
         let mut end_cursor = None;
         while has_next_page {
             let request_body = ProjectsQuery::build_query(projects_query::Variables {
                 after: end_cursor.clone(),
+                login: owner.clone(),
+                is_organization: owner_kind == OwnerKind::Organization,
                 id: id.into(),
-                org: org.clone(),
                 first: FETCH_AMOUNT,
             });
+            let GraphqlResponse { data, errors }: GraphqlResponse<projects_query::ResponseData> =
+                self.post_graphql(&request_body, access_token).await?;
 
-            let res = self
-                .client
-                .post(GITHUB_GRAPHQL_URL)
-                .header("Authorization", format!("Bearer {access_token}"))
-                .json(&request_body)
-                .send()
-                .await
-                .map_err(|_| AppError)?;
-            let response_body: Response<projects_query::ResponseData> =
-                res.json().await.map_err(|_| AppError)?;
-
-            let items = response_body
-                .data
-                .and_then(|data| data.organization)
-                .and_then(|organization| organization.project_v2)
-                .and_then(|project| {
-                    end_cursor = project.items.page_info.end_cursor;
-                    has_next_page = project.items.page_info.has_next_page;
-                    project.items.nodes
-                })
-                .unwrap_or_default();
+            let Some(project) = data.and_then(|data| match owner_kind {
+                OwnerKind::Organization => data
+                    .organization
+                    .and_then(|organization| organization.project_v2),
+                OwnerKind::User => data.user.and_then(|user| user.project_v2),
+            }) else {
+                return Err(missing_project(errors, owner, id));
+            };
+            unmapped_items
+                .warnings
+                .extend(partial_data_warnings(&errors));
+            end_cursor = project.items.page_info.end_cursor;
+            has_next_page = project.items.page_info.has_next_page;
+            unmapped_items.project_id = project.id;
+            let items = project.items.nodes.unwrap_or_default();
 
             for item in items.iter().flatten() {
-                if item.task_type.is_none() {
-                    let Some(
-                        projects_query::ProjectsQueryOrganizationProjectV2ItemsNodesContent::Issue(
-                            issue,
-                        ),
-                    ) = item.content.as_ref()
-                    else {
-                        continue;
-                    };
-                    let Some(author) = issue.author.as_ref() else {
-                        continue;
-                    };
-                    unmapped_items.push(Item {
-                        title: issue.title.clone(),
-                        author: author.login.clone(),
-                        url: issue.url.clone(),
-                    });
+                let missing_fields = missing_fields(required_fields, &item.field_values);
+                if missing_fields.is_empty() {
+                    continue;
                 }
+                let Some(content) = item.content.as_ref() else {
+                    continue;
+                };
+                unmapped_items
+                    .items
+                    .push(unmapped_item(&item.id, content, missing_fields));
             }
         }
         Ok(unmapped_items)
     }
+
+    async fn get_project_id(
+        &self,
+        owner_kind: OwnerKind,
+        owner: String,
+        id: u32,
+        access_token: &str,
+    ) -> Result<String, AppError> {
+        let request_body = ProjectIdQuery::build_query(project_id_query::Variables {
+            login: owner.clone(),
+            is_organization: owner_kind == OwnerKind::Organization,
+            id: id.into(),
+        });
+        let GraphqlResponse { data, errors }: GraphqlResponse<project_id_query::ResponseData> =
+            self.post_graphql(&request_body, access_token).await?;
+        data.and_then(|data| match owner_kind {
+            OwnerKind::Organization => data
+                .organization
+                .and_then(|organization| organization.project_v2)
+                .map(|project| project.id),
+            OwnerKind::User => data
+                .user
+                .and_then(|user| user.project_v2)
+                .map(|project| project.id),
+        })
+        .ok_or_else(|| missing_project(errors, owner, id))
+    }
+
+    async fn get_project_fields(
+        &self,
+        owner_kind: OwnerKind,
+        owner: String,
+        id: u32,
+        access_token: &str,
+    ) -> Result<Vec<ProjectField>, AppError> {
+        let request_body = ProjectFieldsQuery::build_query(project_fields_query::Variables {
+            login: owner.clone(),
+            is_organization: owner_kind == OwnerKind::Organization,
+            id: id.into(),
+        });
+        let GraphqlResponse { data, errors }: GraphqlResponse<project_fields_query::ResponseData> =
+            self.post_graphql(&request_body, access_token).await?;
+        let project = data
+            .and_then(|data| match owner_kind {
+                OwnerKind::Organization => data
+                    .organization
+                    .and_then(|organization| organization.project_v2),
+                OwnerKind::User => data.user.and_then(|user| user.project_v2),
+            })
+            .ok_or_else(|| missing_project(errors, owner, id))?;
+        Ok(project
+            .fields
+            .nodes
+            .iter()
+            .flatten()
+            .flatten()
+            .filter_map(project_field)
+            .collect())
+    }
+
+    async fn get_item_field_values(
+        &self,
+        project_id: &str,
+        item_ids: &[String],
+        field_id: &str,
+        access_token: &str,
+    ) -> Result<HashMap<String, Option<FieldValue>>, AppError> {
+        use item_field_values_query::ItemFieldValuesQueryNodes as Node;
+        let request_body = ItemFieldValuesQuery::build_query(item_field_values_query::Variables {
+            ids: item_ids.to_vec(),
+        });
+        let GraphqlResponse { data, errors }: GraphqlResponse<
+            item_field_values_query::ResponseData,
+        > = self.post_graphql(&request_body, access_token).await?;
+        // Unknown ids come back as nulls next to NOT_FOUND errors, which only make them unknown.
+        let unexpected_errors = errors
+            .iter()
+            .any(|error| error.error_type.as_deref() != Some("NOT_FOUND"));
+        let Some(data) = data.filter(|_| !unexpected_errors) else {
+            return Err(graphql_error(errors));
+        };
+        Ok(data
+            .nodes
+            .into_iter()
+            .flatten()
+            .filter_map(|node| match node {
+                Node::ProjectV2Item(item) if item.project.id == project_id => {
+                    let value = item_field_value(&item.field_values, field_id);
+                    Some((item.id, value))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn update_item_field(
+        &self,
+        project_id: &str,
+        item_id: &str,
+        field_id: &str,
+        value: FieldValue,
+        access_token: &str,
+    ) -> Result<(), AppError> {
+        let request_body =
+            UpdateItemFieldMutation::build_query(update_item_field_mutation::Variables {
+                project_id: project_id.to_string(),
+                item_id: item_id.to_string(),
+                field_id: field_id.to_string(),
+                value: value.into(),
+            });
+        let response_body: GraphqlResponse<update_item_field_mutation::ResponseData> =
+            self.post_graphql(&request_body, access_token).await?;
+        if !response_body.errors.is_empty() {
+            return Err(graphql_error(response_body.errors));
+        }
+        response_body
+            .data
+            .and_then(|data| data.update_project_v2_item_field_value)
+            .and_then(|payload| payload.project_v2_item)
+            .map(|_| ())
+            .ok_or_else(|| AppError::Internal(format!("GitHub did not update item {item_id}")))
+    }
+}
+
+impl GithubService {
+    fn installation_token_slot(&self, organization: &str) -> InstallationTokenSlot {
+        self.installation_tokens
+            .lock()
+            .expect("Failed to lock installation tokens")
+            .entry(organization.to_string())
+            .or_default()
+            .clone()
+    }
+
+    async fn post_graphql<B: Serialize, R: DeserializeOwned>(
+        &self,
+        request_body: &B,
+        access_token: &str,
+    ) -> Result<GraphqlResponse<R>, AppError> {
+        self.client
+            .post(format!("{}/graphql", self.api_url))
+            .header("Authorization", format!("Bearer {access_token}"))
+            .json(request_body)
+            .send()
+            .await
+            .map_err(AppError::from)
+            .and_then(check_status)?
+            .json()
+            .await
+            .map_err(AppError::from)
+    }
+}
+
+impl From<FieldValue> for update_item_field_mutation::ProjectV2FieldValue {
+    fn from(value: FieldValue) -> Self {
+        let mut field_value = update_item_field_mutation::ProjectV2FieldValue {
+            date: None,
+            iteration_id: None,
+            number: None,
+            single_select_option_id: None,
+            text: None,
+        };
+        match value {
+            FieldValue::SingleSelectOptionId(id) => field_value.single_select_option_id = Some(id),
+            FieldValue::IterationId(id) => field_value.iteration_id = Some(id),
+            FieldValue::Number(number) => field_value.number = Some(number),
+            FieldValue::Date(date) => field_value.date = Some(date),
+            FieldValue::Text(text) => field_value.text = Some(text),
+        }
+        field_value
+    }
+}
+
+fn app_jwt(client_id: &str, private_key: &str) -> Result<String, AppError> {
+    let now = Utc::now();
+    let claims = AppClaims {
+        iat: (now - TimeDelta::seconds(60)).timestamp(),
+        exp: (now + APP_JWT_LIFETIME).timestamp(),
+        iss: client_id.to_string(),
+    };
+    let key = EncodingKey::from_rsa_pem(private_key.as_bytes())
+        .map_err(|e| AppError::Internal(format!("Invalid GitHub App private key: {e}")))?;
+    jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+        .map_err(|e| AppError::Internal(format!("Failed to sign GitHub App JWT: {e}")))
+}
+
+// GitHub reports exhausted rate limits as 403 or 429 with the reset time in the headers.
+fn check_status(response: reqwest::Response) -> Result<reqwest::Response, AppError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<i64>().ok())
+    };
+    let retry_after = header(header::RETRY_AFTER.as_str());
+    let rate_limit_exhausted = header("x-ratelimit-remaining") == Some(0);
+    if status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && (rate_limit_exhausted || retry_after.is_some()))
+    {
+        let reset_at = retry_after
+            .map(|seconds| Utc::now() + TimeDelta::seconds(seconds))
+            .or_else(|| {
+                header("x-ratelimit-reset").and_then(|reset| DateTime::from_timestamp(reset, 0))
+            });
+        return Err(AppError::GithubRateLimited(reset_at));
+    }
+    Err(AppError::GithubStatus(status))
+}
+
+// Nodes GitHub could not resolve come back as nulls next to an error, the rest of the page is usable.
+fn partial_data_warnings(errors: &[GraphqlError]) -> impl Iterator<Item = String> + '_ {
+    errors.iter().map(GraphqlError::to_string)
+}
+
+fn missing_project(errors: Vec<GraphqlError>, owner: String, id: u32) -> AppError {
+    if errors.is_empty() {
+        return AppError::ProjectNotFound { owner, id };
+    }
+    graphql_error(errors)
+}
+
+fn missing_owner(errors: Vec<GraphqlError>, owner: String) -> AppError {
+    if errors.is_empty() {
+        return AppError::OwnerNotFound(owner);
+    }
+    graphql_error(errors)
+}
+
+fn graphql_error(errors: Vec<GraphqlError>) -> AppError {
+    let has_type = |error_types: &[&str]| {
+        errors.iter().any(|error| {
+            error
+                .error_type
+                .as_deref()
+                .is_some_and(|error_type| error_types.contains(&error_type))
+        })
+    };
+    if has_type(&["RATE_LIMITED"]) {
+        AppError::GithubRateLimited(None)
+    } else if has_type(&["FORBIDDEN", "INSUFFICIENT_SCOPES"]) {
+        AppError::GithubForbidden(errors)
+    } else if has_type(&["NOT_FOUND"]) {
+        AppError::GithubNotFound(errors)
+    } else {
+        AppError::GraphqlErrors(errors)
+    }
+}
+
+fn project_field(field: &project_fields_query::ProjectV2FieldDetails) -> Option<ProjectField> {
+    use project_fields_query::{ProjectV2FieldDetails as Field, ProjectV2FieldType};
+    let field_type = |data_type: &ProjectV2FieldType| match data_type {
+        ProjectV2FieldType::SINGLE_SELECT => Some(FieldType::SingleSelect),
+        ProjectV2FieldType::ITERATION => Some(FieldType::Iteration),
+        ProjectV2FieldType::NUMBER => Some(FieldType::Number),
+        ProjectV2FieldType::DATE => Some(FieldType::Date),
+        ProjectV2FieldType::TEXT => Some(FieldType::Text),
+        _ => None,
+    };
+    let iteration = |iteration: &project_fields_query::ProjectV2Iteration| Iteration {
+        id: iteration.id.clone(),
+        title: iteration.title.clone(),
+        start_date: iteration.start_date,
+        duration: iteration.duration,
+    };
+    let field = match field {
+        Field::ProjectV2Field(field) => ProjectField {
+            id: field.id.clone(),
+            name: field.name.clone(),
+            field_type: field_type(&field.data_type)?,
+            options: vec![],
+            iteration_configuration: None,
+        },
+        Field::ProjectV2IterationField(field) => ProjectField {
+            id: field.id.clone(),
+            name: field.name.clone(),
+            field_type: field_type(&field.data_type)?,
+            options: field
+                .configuration
+                .iterations
+                .iter()
+                .map(|iteration| FieldOption {
+                    id: iteration.id.clone(),
+                    name: iteration.title.clone(),
+                })
+                .collect(),
+            iteration_configuration: Some(IterationConfiguration {
+                duration: field.configuration.duration,
+                start_day: field.configuration.start_day,
+                iterations: field
+                    .configuration
+                    .iterations
+                    .iter()
+                    .map(iteration)
+                    .collect(),
+                completed_iterations: field
+                    .configuration
+                    .completed_iterations
+                    .iter()
+                    .map(iteration)
+                    .collect(),
+            }),
+        },
+        Field::ProjectV2SingleSelectField(field) => ProjectField {
+            id: field.id.clone(),
+            name: field.name.clone(),
+            field_type: field_type(&field.data_type)?,
+            options: field
+                .options
+                .iter()
+                .map(|option| FieldOption {
+                    id: option.id.clone(),
+                    name: option.name.clone(),
+                })
+                .collect(),
+            iteration_configuration: None,
+        },
+    };
+    Some(field)
+}
+
+fn unmapped_item(
+    id: &str,
+    content: &projects_query::ProjectV2ItemsItemsNodesContent,
+    missing_fields: Vec<String>,
+) -> Item {
+    use projects_query::ProjectV2ItemsItemsNodesContent as Content;
+    match content {
+        Content::Issue(issue) => Item {
+            id: id.to_string(),
+            kind: ItemKind::Issue,
+            number: Some(issue.number),
+            title: issue.title.clone(),
+            author: issue.author.as_ref().map(|author| author.login.clone()),
+            avatar_url: issue
+                .author
+                .as_ref()
+                .map(|author| author.avatar_url.clone()),
+            url: Some(issue.url.clone()),
+            created_at: issue.created_at,
+            missing_fields,
+        },
+        Content::PullRequest(pull_request) => Item {
+            id: id.to_string(),
+            kind: ItemKind::PullRequest,
+            number: Some(pull_request.number),
+            title: pull_request.title.clone(),
+            author: pull_request
+                .author
+                .as_ref()
+                .map(|author| author.login.clone()),
+            avatar_url: pull_request
+                .author
+                .as_ref()
+                .map(|author| author.avatar_url.clone()),
+            url: Some(pull_request.url.clone()),
+            created_at: pull_request.created_at,
+            missing_fields,
+        },
+        Content::DraftIssue(draft_issue) => Item {
+            id: id.to_string(),
+            kind: ItemKind::DraftIssue,
+            number: None,
+            title: draft_issue.title.clone(),
+            author: draft_issue
+                .creator
+                .as_ref()
+                .map(|creator| creator.login.clone()),
+            avatar_url: draft_issue
+                .creator
+                .as_ref()
+                .map(|creator| creator.avatar_url.clone()),
+            url: None,
+            created_at: draft_issue.created_at,
+            missing_fields,
+        },
+    }
+}
+
+fn missing_fields(
+    required_fields: &[RequiredField],
+    field_values: &projects_query::ProjectV2ItemsItemsNodesFieldValues,
+) -> Vec<String> {
+    let set_fields: Vec<(&str, FieldType)> = field_values
+        .nodes
+        .iter()
+        .flatten()
+        .flatten()
+        .filter_map(set_field)
+        .collect();
+    required_fields
+        .iter()
+        .filter(|required| {
+            !set_fields.iter().any(|(name, field_type)| {
+                *name == required.name && *field_type == required.field_type
+            })
+        })
+        .map(|required| required.name.clone())
+        .collect()
+}
+
+fn set_field(
+    value: &projects_query::ProjectV2ItemsItemsNodesFieldValuesNodes,
+) -> Option<(&str, FieldType)> {
+    use projects_query::ProjectV2ItemsItemsNodesFieldValuesNodes as Value;
+    match value {
+        Value::ProjectV2ItemFieldSingleSelectValue(value) => {
+            Some((field_name(&value.field), FieldType::SingleSelect))
+        }
+        Value::ProjectV2ItemFieldIterationValue(value) => {
+            Some((field_name(&value.field), FieldType::Iteration))
+        }
+        Value::ProjectV2ItemFieldNumberValue(value) => {
+            Some((field_name(&value.field), FieldType::Number))
+        }
+        Value::ProjectV2ItemFieldDateValue(value) => {
+            Some((field_name(&value.field), FieldType::Date))
+        }
+        Value::ProjectV2ItemFieldTextValue(value) => {
+            Some((field_name(&value.field), FieldType::Text))
+        }
+        _ => None,
+    }
+}
+
+fn item_field_value(
+    field_values: &item_field_values_query::ItemFieldValuesQueryNodesOnProjectV2ItemFieldValues,
+    field_id: &str,
+) -> Option<FieldValue> {
+    use item_field_values_query::ItemFieldValuesQueryNodesOnProjectV2ItemFieldValuesNodes as Value;
+    field_values
+        .nodes
+        .iter()
+        .flatten()
+        .flatten()
+        .find_map(|value| match value {
+            Value::ProjectV2ItemFieldSingleSelectValue(value)
+                if field_id_of(&value.field) == field_id =>
+            {
+                value
+                    .option_id
+                    .clone()
+                    .map(FieldValue::SingleSelectOptionId)
+            }
+            Value::ProjectV2ItemFieldIterationValue(value)
+                if field_id_of(&value.field) == field_id =>
+            {
+                Some(FieldValue::IterationId(value.iteration_id.clone()))
+            }
+            Value::ProjectV2ItemFieldNumberValue(value)
+                if field_id_of(&value.field) == field_id =>
+            {
+                value.number.map(FieldValue::Number)
+            }
+            Value::ProjectV2ItemFieldDateValue(value) if field_id_of(&value.field) == field_id => {
+                value.date.map(FieldValue::Date)
+            }
+            Value::ProjectV2ItemFieldTextValue(value) if field_id_of(&value.field) == field_id => {
+                value.text.clone().map(FieldValue::Text)
+            }
+            _ => None,
+        })
+}
+
+fn field_id_of(field: &item_field_values_query::ProjectV2FieldId) -> &str {
+    match field {
+        item_field_values_query::ProjectV2FieldId::ProjectV2Field(field) => &field.id,
+        item_field_values_query::ProjectV2FieldId::ProjectV2IterationField(field) => &field.id,
+        item_field_values_query::ProjectV2FieldId::ProjectV2SingleSelectField(field) => &field.id,
+    }
+}
+
+fn field_name(field: &projects_query::ProjectV2FieldName) -> &str {
+    match field {
+        projects_query::ProjectV2FieldName::ProjectV2Field(field) => &field.name,
+        projects_query::ProjectV2FieldName::ProjectV2IterationField(field) => &field.name,
+        projects_query::ProjectV2FieldName::ProjectV2SingleSelectField(field) => &field.name,
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::collections::HashMap;
+
+    use chrono::Utc;
+    use reqwest::StatusCode;
+
+    use super::{
+        FieldOption, FieldValue, Github, GraphqlError, Item, ItemKind, OauthResponse, OwnerKind,
+        ProjectField, ProjectList, ProjectSummary, UnmappedItems, UserResponse,
+    };
+    use crate::{
+        controller::AppError,
+        model::{FieldType, RequiredField},
+    };
+
+    pub struct MockGithubService;
+    impl Github for MockGithubService {
+        fn new() -> MockGithubService {
+            MockGithubService {}
+        }
+        async fn post_login_oauth_access_token(
+            &self,
+            _: &str,
+            _: &str,
+            _: &str,
+            _: &str,
+        ) -> Result<OauthResponse, AppError> {
+            Ok(OauthResponse {
+                access_token: "access_token".to_string(),
+                refresh_token: "refresh_token".to_string(),
+                expires_in: 28800,
+                refresh_token_expires_in: Some(15897600),
+            })
+        }
+
+        async fn post_login_oauth_refresh_token(
+            &self,
+            _: &str,
+            refresh_token: &str,
+            _: &str,
+        ) -> Result<OauthResponse, AppError> {
+            Ok(OauthResponse {
+                access_token: format!("refreshed_{refresh_token}"),
+                refresh_token: "new_refresh_token".to_string(),
+                expires_in: 28800,
+                refresh_token_expires_in: Some(15897600),
+            })
+        }
+
+        async fn delete_app_grant(
+            &self,
+            _: &str,
+            _: &str,
+            access_token: &str,
+        ) -> Result<(), AppError> {
+            if access_token == "revoked_access_token" {
+                return Err(AppError::GithubStatus(StatusCode::NOT_FOUND));
+            }
+            Ok(())
+        }
+
+        async fn get_user(&self, _: &str) -> Result<UserResponse, AppError> {
+            Ok(UserResponse {
+                login: "user_login".to_owned(),
+            })
+        }
+
+        async fn get_installation_token(
+            &self,
+            _: &str,
+            _: &str,
+            organization: &str,
+        ) -> Result<String, AppError> {
+            if organization == "uninstalled_org" {
+                return Err(AppError::GithubStatus(StatusCode::NOT_FOUND));
+            }
+            Ok("installation_token".to_string())
+        }
+
+        async fn is_organization_member(
+            &self,
+            organization: &str,
+            _: &str,
+            _: &str,
+        ) -> Result<bool, AppError> {
+            if organization == "unreadable_org" {
+                return Err(AppError::GithubStatus(StatusCode::FORBIDDEN));
+            }
+            Ok(organization == "allowed_org")
+        }
+
+        async fn is_team_member(
+            &self,
+            organization: &str,
+            team: &str,
+            _: &str,
+            _: &str,
+        ) -> Result<bool, AppError> {
+            Ok(organization == "allowed_org" && team == "allowed_team")
+        }
+
+        async fn list_projects(
+            &self,
+            _owner_kind: OwnerKind,
+            owner: String,
+            _access_token: &str,
+        ) -> Result<ProjectList, AppError> {
+            if owner == "missing_owner" {
+                return Err(AppError::OwnerNotFound(owner));
+            }
+            Ok(ProjectList {
+                projects: vec![ProjectSummary {
+                    number: 1,
+                    title: "Roadmap".to_string(),
+                    closed: false,
+                    item_count: 1,
+                }],
+                warnings: (owner == "partial_owner")
+                    .then(|| "Resource not accessible by integration".to_string())
+                    .into_iter()
+                    .collect(),
+            })
+        }
+
+        async fn get_unmapped_items(
+            &self,
+            _owner_kind: OwnerKind,
+            owner: String,
+            id: u32,
+            required_fields: &[RequiredField],
+            _access_token: &str,
+        ) -> Result<UnmappedItems, AppError> {
+            if owner == "missing_owner" {
+                return Err(AppError::ProjectNotFound { owner, id });
+            }
+            Ok(UnmappedItems {
+                project_id: "PVT_project".to_string(),
+                fields: vec![],
+                items: vec![Item {
+                    id: "PVTI_item".to_string(),
+                    kind: ItemKind::Issue,
+                    number: Some(1),
+                    title: "Unmapped issue".to_string(),
+                    author: Some("user_login".to_string()),
+                    avatar_url: None,
+                    url: None,
+                    created_at: Utc::now(),
+                    missing_fields: required_fields
+                        .iter()
+                        .map(|required_field| required_field.name.clone())
+                        .collect(),
+                }],
+                warnings: vec![],
+            })
+        }
+
+        async fn get_project_id(
+            &self,
+            _owner_kind: OwnerKind,
+            _owner: String,
+            _id: u32,
+            _access_token: &str,
+        ) -> Result<String, AppError> {
+            Ok("PVT_project".to_string())
+        }
+
+        async fn get_project_fields(
+            &self,
+            _owner_kind: OwnerKind,
+            _owner: String,
+            _id: u32,
+            _access_token: &str,
+        ) -> Result<Vec<ProjectField>, AppError> {
+            Ok(vec![ProjectField {
+                id: "PVTSSF_status".to_string(),
+                name: "Status".to_string(),
+                field_type: FieldType::SingleSelect,
+                options: vec![
+                    FieldOption {
+                        id: "todo".to_string(),
+                        name: "Todo".to_string(),
+                    },
+                    FieldOption {
+                        id: "done".to_string(),
+                        name: "Done".to_string(),
+                    },
+                ],
+                iteration_configuration: None,
+            }])
+        }
+
+        async fn get_item_field_values(
+            &self,
+            _project_id: &str,
+            item_ids: &[String],
+            _field_id: &str,
+            _access_token: &str,
+        ) -> Result<HashMap<String, Option<FieldValue>>, AppError> {
+            Ok(item_ids
+                .iter()
+                .filter(|item_id| item_id.starts_with("PVTI_"))
+                .map(|item_id| {
+                    let value = (item_id == "PVTI_done")
+                        .then(|| FieldValue::SingleSelectOptionId("done".to_string()));
+                    (item_id.clone(), value)
+                })
+                .collect())
+        }
+
+        async fn update_item_field(
+            &self,
+            _project_id: &str,
+            item_id: &str,
+            _field_id: &str,
+            _value: FieldValue,
+            _access_token: &str,
+        ) -> Result<(), AppError> {
+            if item_id == "missing_item" {
+                return Err(AppError::GithubNotFound(vec![GraphqlError {
+                    message: "Could not resolve to a node with the global id of 'missing_item'"
+                        .to_string(),
+                    error_type: Some("NOT_FOUND".to_string()),
+                    path: vec![],
+                }]));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        Json, Router,
+        extract::Path,
+        http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+        routing::{get, post},
+    };
+    use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+    use serde_json::json;
+
+    use chrono::{TimeDelta, Utc};
+
+    use super::{
+        FieldValue, Github, GithubService, GraphqlResponse, InstallationToken, ItemKind,
+        ItemUpdateStatus, check_status, graphql_error, missing_fields, missing_owner,
+        mock::MockGithubService, projects_query, unmapped_item, update_item_field_mutation,
+    };
+    use crate::{
+        controller::AppError,
+        model::{AllowedGroup, FieldType, RequiredField},
+    };
+
+    static TEST_APP_PRIVATE_KEY: &str = include_str!("../testdata/github_app_key.pem");
+    static TEST_APP_PUBLIC_KEY: &str = include_str!("../testdata/github_app_key.pub.pem");
+
+    #[test]
+    fn missing_fields_reports_unset_and_mistyped_fields() {
+        let field_values: projects_query::ProjectV2ItemsItemsNodesFieldValues =
+            serde_json::from_value(json!({
+                "nodes": [
+                    {
+                        "__typename": "ProjectV2ItemFieldSingleSelectValue",
+                        "field": { "__typename": "ProjectV2SingleSelectField", "name": "Task type" }
+                    },
+                    {
+                        "__typename": "ProjectV2ItemFieldTextValue",
+                        "field": { "__typename": "ProjectV2Field", "name": "Estimate" }
+                    },
+                    { "__typename": "ProjectV2ItemFieldLabelValue" }
+                ]
+            }))
+            .unwrap();
+        let required_fields = vec![
+            RequiredField {
+                name: "Task type".to_string(),
+                field_type: FieldType::SingleSelect,
+            },
+            RequiredField {
+                name: "Estimate".to_string(),
+                field_type: FieldType::Number,
+            },
+            RequiredField {
+                name: "Sprint".to_string(),
+                field_type: FieldType::Iteration,
+            },
+        ];
+
+        assert_eq!(
+            missing_fields(&required_fields, &field_values),
+            vec!["Estimate".to_string(), "Sprint".to_string()]
+        );
+    }
+
+    #[test]
+    fn unmapped_item_keeps_items_from_deleted_authors() {
+        let content: projects_query::ProjectV2ItemsItemsNodesContent =
+            serde_json::from_value(json!({
+                "__typename": "Issue",
+                "title": "Orphaned ticket",
+                "number": 42,
+                "url": "https://github.com/acme/repo/issues/42",
+                "author": null,
+                "createdAt": "2021-03-04T05:06:07Z"
+            }))
+            .unwrap();
+
+        let item = unmapped_item("PVTI_1", &content, vec!["Task type".to_string()]);
+
+        assert_eq!(item.kind, ItemKind::Issue);
+        assert_eq!(item.number, Some(42));
+        assert_eq!(item.author, None);
+        assert_eq!(item.avatar_url, None);
+    }
+
+    #[test]
+    fn field_value_only_sends_the_chosen_value() {
+        let value: FieldValue =
+            serde_json::from_value(json!({ "single_select_option_id": "abc123" })).unwrap();
+
+        let field_value: update_item_field_mutation::ProjectV2FieldValue = value.into();
+
+        assert_eq!(
+            serde_json::to_value(field_value).unwrap(),
+            json!({ "singleSelectOptionId": "abc123" })
+        );
+    }
+
+    #[tokio::test]
+    async fn update_items_field_reports_each_item_in_order() {
+        let results = MockGithubService
+            .update_items_field(
+                "PVT_project",
+                vec![
+                    "PVTI_1".to_string(),
+                    "missing_item".to_string(),
+                    "PVTI_2".to_string(),
+                ],
+                "PVTF_field",
+                FieldValue::Text("text".to_string()),
+                "access_token",
+            )
+            .await;
+
+        let statuses: Vec<(&str, ItemUpdateStatus)> = results
+            .iter()
+            .map(|result| (result.item_id.as_str(), result.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("PVTI_1", ItemUpdateStatus::Updated),
+                ("missing_item", ItemUpdateStatus::Failed),
+                ("PVTI_2", ItemUpdateStatus::Updated),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn installation_tokens_are_cached_until_they_expire() {
+        let github = GithubService::new();
+        *github.installation_token_slot("acme").lock().await = Some(InstallationToken {
+            token: "installation_token".to_string(),
+            expires_at: Utc::now() + TimeDelta::hours(1),
+        });
+        *github.installation_token_slot("expiring").lock().await = Some(InstallationToken {
+            token: "expiring_token".to_string(),
+            expires_at: Utc::now() + TimeDelta::minutes(1),
+        });
+
+        assert_eq!(
+            github
+                .get_installation_token("client_id", "invalid_key", "acme")
+                .await
+                .unwrap(),
+            "installation_token"
+        );
+        assert!(
+            github
+                .get_installation_token("client_id", "invalid_key", "expiring")
+                .await
+                .is_err()
+        );
+    }
+
+    // Serves the installation lookup and token exchange, only for JWTs signed with the test app key.
+    async fn fake_github_api(created_tokens: Arc<AtomicUsize>) -> String {
+        let check_jwt = |headers: &HeaderMap| {
+            let jwt = headers
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .unwrap_or_default();
+            let mut validation = Validation::new(Algorithm::RS256);
+            validation.set_issuer(&["client_id"]);
+            let key = DecodingKey::from_rsa_pem(TEST_APP_PUBLIC_KEY.as_bytes()).unwrap();
+            jsonwebtoken::decode::<serde_json::Value>(jwt, &key, &validation)
+                .map(|_| ())
+                .map_err(|_| StatusCode::UNAUTHORIZED)
+        };
+        let router = Router::new()
+            .route(
+                "/orgs/{organization}/installation",
+                get(move |headers: HeaderMap| async move {
+                    check_jwt(&headers)?;
+                    Ok::<_, StatusCode>(Json(json!({ "id": 42 })))
+                }),
+            )
+            .route(
+                "/app/installations/{installation_id}/access_tokens",
+                post(
+                    move |Path(installation_id): Path<i64>, headers: HeaderMap| async move {
+                        check_jwt(&headers)?;
+                        created_tokens.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Ok::<_, StatusCode>(Json(json!({
+                            "token": format!("installation_token_{installation_id}"),
+                            "expires_at": Utc::now() + TimeDelta::hours(1),
+                        })))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn installation_tokens_are_exchanged_once_for_concurrent_requests() {
+        let created_tokens = Arc::new(AtomicUsize::new(0));
+        let mut github = GithubService::new();
+        github.api_url = fake_github_api(created_tokens.clone()).await;
+
+        let (first, second) = tokio::join!(
+            github.get_installation_token("client_id", TEST_APP_PRIVATE_KEY, "acme"),
+            github.get_installation_token("client_id", TEST_APP_PRIVATE_KEY, "acme"),
+        );
+        assert_eq!(first.unwrap(), "installation_token_42");
+        assert_eq!(second.unwrap(), "installation_token_42");
+        assert_eq!(created_tokens.load(Ordering::SeqCst), 1);
+
+        github
+            .get_installation_token("client_id", TEST_APP_PRIVATE_KEY, "other")
+            .await
+            .unwrap();
+        assert_eq!(created_tokens.load(Ordering::SeqCst), 2);
+
+        let wrong_app = github
+            .get_installation_token("other_client_id", TEST_APP_PRIVATE_KEY, "unknown")
+            .await;
+        assert!(matches!(
+            wrong_app,
+            Err(AppError::GithubStatus(StatusCode::UNAUTHORIZED))
+        ));
+    }
+
+    #[test]
+    fn exhausted_rate_limits_are_reported_with_their_reset_time() {
+        let response = reqwest::Response::from(
+            axum::http::Response::builder()
+                .status(403)
+                .header("x-ratelimit-remaining", "0")
+                .header("x-ratelimit-reset", "1750000000")
+                .body("")
+                .unwrap(),
+        );
+
+        let Err(AppError::GithubRateLimited(Some(reset_at))) = check_status(response) else {
+            panic!("Expected a rate limit error");
+        };
+        assert_eq!(reset_at.timestamp(), 1750000000);
+
+        let response = reqwest::Response::from(
+            axum::http::Response::builder()
+                .status(403)
+                .body("")
+                .unwrap(),
+        );
+        assert!(matches!(
+            check_status(response),
+            Err(AppError::GithubStatus(status)) if status == 403
+        ));
+    }
+
+    #[tokio::test]
+    async fn unreadable_groups_do_not_hide_later_memberships() {
+        let organization =
+            |organization: &str| AllowedGroup::Organization(organization.to_string());
+        let is_allowed = async |allowed_groups: &[AllowedGroup]| {
+            MockGithubService
+                .is_allowed(allowed_groups, "user_login", "access_token")
+                .await
+        };
+
+        assert!(
+            is_allowed(&[
+                organization("unreadable_org"),
+                AllowedGroup::Team {
+                    organization: "allowed_org".to_string(),
+                    team: "allowed_team".to_string(),
+                },
+            ])
+            .await
+            .unwrap()
+        );
+        assert!(!is_allowed(&[organization("other_org")]).await.unwrap());
+        assert!(matches!(
+            is_allowed(&[organization("unreadable_org"), organization("other_org")]).await,
+            Err(AppError::GithubStatus(_))
+        ));
+    }
+
+    #[test]
+    fn graphql_errors_are_mapped_by_their_type() {
+        let response: GraphqlResponse<serde_json::Value> = serde_json::from_value(json!({
+            "data": { "organization": { "projectV2": null } },
+            "errors": [{
+                "type": "NOT_FOUND",
+                "path": ["organization", "projectV2"],
+                "message": "Could not resolve to a ProjectV2 with the number 7."
+            }]
+        }))
+        .unwrap();
+        assert_eq!(
+            response.errors[0].to_string(),
+            "Could not resolve to a ProjectV2 with the number 7. (at organization.projectV2)"
+        );
+        assert!(matches!(
+            graphql_error(response.errors),
+            AppError::GithubNotFound(_)
+        ));
+
+        let response: GraphqlResponse<serde_json::Value> = serde_json::from_value(json!({
+            "data": null,
+            "errors": [{
+                "type": "INSUFFICIENT_SCOPES",
+                "message": "Your token has not been granted the required scopes."
+            }]
+        }))
+        .unwrap();
+        assert!(matches!(
+            graphql_error(response.errors),
+            AppError::GithubForbidden(_)
+        ));
+
+        let response: GraphqlResponse<serde_json::Value> = serde_json::from_value(json!({
+            "errors": [{ "message": "Something went wrong" }]
+        }))
+        .unwrap();
+        assert!(matches!(
+            graphql_error(response.errors),
+            AppError::GraphqlErrors(_)
+        ));
+
+        assert!(matches!(
+            missing_owner(vec![], "ghost".to_string()),
+            AppError::OwnerNotFound(owner) if owner == "ghost"
+        ));
+    }
 }
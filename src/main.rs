@@ -1,17 +1,22 @@
-use app::{App, AppStartError};
+use app::{App, AppConfig, AppStartError};
 use repository::Postgres;
 use secrets::Secrets;
-use secrets::b64_to_string;
+use secrets::{b64_to_bytes, b64_to_string};
 use serde::Deserialize;
 
 mod app;
 mod controller;
+mod crypto;
 mod extractors;
+#[cfg(test)]
+mod fixtures;
 mod github;
 mod model;
 mod repository;
 mod routes;
+mod scanner;
 mod secrets;
+mod sweeper;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "UPPERCASE")]
@@ -20,6 +25,12 @@ pub struct AppSecrets {
     client_secret: String,
     #[serde(deserialize_with = "b64_to_string")]
     pg_url: String,
+    #[serde(deserialize_with = "b64_to_bytes")]
+    cookie_key: Vec<u8>,
+    #[serde(deserialize_with = "b64_to_string")]
+    token_keys: String,
+    #[serde(default, deserialize_with = "b64_to_string")]
+    github_app_private_key: String,
 }
 
 #[tokio::main]
@@ -27,7 +38,7 @@ async fn main() -> Result<(), AppStartError> {
     tracing_subscriber::fmt::init();
     let secrets: &AppSecrets = Box::leak(Secrets::load());
     let repository = Postgres::new(secrets).await;
-    let app = App::new("localhost".to_string(), 3000);
+    let app = App::new("localhost".to_string(), 3000, AppConfig::from_env()?);
     app.run(secrets, repository).await?;
     Ok(())
 }
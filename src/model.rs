@@ -1,3 +1,7 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
 use crate::github::DateTime;
 
 #[derive(Debug)]
@@ -7,6 +11,56 @@ pub struct Session {
     pub access_token: String,
     pub refresh_token: String,
     pub expires_at: DateTime,
+    pub refresh_expires_at: DateTime,
+    pub last_used_at: DateTime,
+    pub access_checked_at: DateTime,
+}
+
+#[derive(Debug)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: DateTime,
+    pub last_used_at: DateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: i32,
+    pub session_id: String,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    Read,
+    Write,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "write" => Ok(ApiScope::Write),
+            other => Err(format!("Unknown API scope {other}")),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -14,3 +68,136 @@ pub struct User {
     pub id: i32,
     pub github_login: String,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Triager,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Triager => "triager",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "viewer" => Ok(Role::Viewer),
+            "triager" => Ok(Role::Triager),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role {other}")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RoleGrant {
+    pub user_id: i32,
+    pub github_login: String,
+    pub organization: Option<String>,
+    pub role: Role,
+}
+
+#[derive(Debug)]
+pub struct RecentProject {
+    pub owner_kind: String,
+    pub owner: String,
+    pub project_number: i32,
+    pub viewed_at: DateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectSettings {
+    pub owner: String,
+    pub project_number: i32,
+    pub required_fields: Vec<RequiredField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    SingleSelect,
+    Iteration,
+    Number,
+    Date,
+    Text,
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single_select" => Ok(FieldType::SingleSelect),
+            "iteration" => Ok(FieldType::Iteration),
+            "number" => Ok(FieldType::Number),
+            "date" => Ok(FieldType::Date),
+            "text" => Ok(FieldType::Text),
+            other => Err(format!("Unknown field type {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequiredField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+}
+
+impl FromStr for RequiredField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, field_type) = match s.rsplit_once(':') {
+            Some((name, field_type)) => (name, field_type.parse()?),
+            None => (s, FieldType::SingleSelect),
+        };
+        Ok(RequiredField {
+            name: name.trim().to_string(),
+            field_type,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllowedGroup {
+    Organization(String),
+    Team { organization: String, team: String },
+}
+
+impl FromStr for AllowedGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once('/') {
+            Some((organization, team)) if !organization.is_empty() && !team.is_empty() => {
+                Ok(AllowedGroup::Team {
+                    organization: organization.to_string(),
+                    team: team.to_string(),
+                })
+            }
+            None if !s.trim().is_empty() => Ok(AllowedGroup::Organization(s.trim().to_string())),
+            _ => Err(format!("Invalid organization or team {s}")),
+        }
+    }
+}
+
+impl Display for AllowedGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllowedGroup::Organization(organization) => write!(f, "{organization}"),
+            AllowedGroup::Team { organization, team } => write!(f, "{organization}/{team}"),
+        }
+    }
+}
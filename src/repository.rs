@@ -2,14 +2,23 @@ use std::{error::Error, fmt::Display};
 
 use crate::{
     AppSecrets,
+    crypto::{EncryptedTokens, TokenKeys},
     github::DateTime,
-    model::{Session, User},
+    model::{
+        ApiScope, ApiToken, ProjectSettings, RecentProject, RequiredField, Role, RoleGrant,
+        Session, SessionInfo, User,
+    },
 };
 use mockall::automock;
-use sqlx::{PgPool, postgres::PgPoolOptions, types::Uuid};
+use sqlx::{
+    PgPool,
+    postgres::PgPoolOptions,
+    types::{Json, Uuid, uuid::Builder},
+};
 
 pub struct Postgres {
     pool: PgPool,
+    token_keys: TokenKeys,
 }
 
 impl Postgres {
@@ -19,18 +28,234 @@ impl Postgres {
             .connect(&secrets.pg_url)
             .await
             .expect("Failed to connect to postgres");
-        Self { pool }
+        let token_keys = TokenKeys::parse(&secrets.token_keys).expect("Failed to load token keys");
+        let postgres = Self { pool, token_keys };
+        let encrypted = postgres
+            .encrypt_plaintext_sessions()
+            .await
+            .expect("Failed to encrypt plaintext sessions");
+        if encrypted > 0 {
+            tracing::info!("Encrypted the tokens of {} existing sessions", encrypted);
+        }
+        postgres
+    }
+
+    // Dormant sessions would otherwise keep plaintext tokens until they are used or swept.
+    async fn encrypt_plaintext_sessions(&self) -> Result<usize, RepositoryError> {
+        let rows = sqlx::query_as!(
+            SessionRow,
+            "SELECT id, user_id, access_token, refresh_token, token_key_id, data_key, expires_at, refresh_expires_at, last_used_at, access_checked_at
+            FROM sessions
+            WHERE token_key_id IS NULL"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetSessionsError)?;
+        let encrypted = rows.len();
+        for row in rows {
+            self.encrypt_plaintext_session(row).await?;
+        }
+        Ok(encrypted)
+    }
+
+    // Rows without a key id predate token encryption and still hold plaintext tokens.
+    fn decrypt_session(&self, row: SessionRow) -> Result<Session, RepositoryError> {
+        let (access_token, refresh_token) = match (row.token_key_id, row.data_key) {
+            (Some(key_id), Some(data_key)) => self
+                .token_keys
+                .decrypt(
+                    &row.id,
+                    &EncryptedTokens {
+                        key_id,
+                        data_key,
+                        access_token: row.access_token,
+                        refresh_token: row.refresh_token,
+                    },
+                )
+                .map_err(|_| RepositoryError::FailedToDecryptTokensError)?,
+            _ => (row.access_token, row.refresh_token),
+        };
+        Ok(Session {
+            id: row.id,
+            user_id: row.user_id,
+            access_token,
+            refresh_token,
+            expires_at: row.expires_at,
+            refresh_expires_at: row.refresh_expires_at,
+            last_used_at: row.last_used_at,
+            access_checked_at: row.access_checked_at,
+        })
+    }
+
+    async fn encrypt_plaintext_session(&self, row: SessionRow) -> Result<Session, RepositoryError> {
+        let session = self.decrypt_session(row)?;
+        let encrypted_tokens = self
+            .token_keys
+            .encrypt(&session.id, &session.access_token, &session.refresh_token)
+            .map_err(|_| RepositoryError::FailedToEncryptTokensError)?;
+        let session_id = Uuid::parse_str(&session.id)
+            .map_err(|_| RepositoryError::FailedToUpdateSessionError)?;
+        sqlx::query!(
+            "UPDATE sessions
+            SET access_token = $2, refresh_token = $3, token_key_id = $4, data_key = $5
+            WHERE id = $1 AND token_key_id IS NULL",
+            session_id,
+            encrypted_tokens.access_token,
+            encrypted_tokens.refresh_token,
+            encrypted_tokens.key_id,
+            encrypted_tokens.data_key
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToUpdateSessionError)?;
+        Ok(session)
+    }
+}
+
+struct ApiTokenRow {
+    id: String,
+    user_id: i32,
+    session_id: String,
+    name: String,
+    scopes: Vec<String>,
+    expires_at: DateTime,
+    created_at: DateTime,
+}
+
+impl From<ApiTokenRow> for ApiToken {
+    fn from(row: ApiTokenRow) -> Self {
+        ApiToken {
+            id: row.id,
+            user_id: row.user_id,
+            session_id: row.session_id,
+            name: row.name,
+            scopes: row
+                .scopes
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+            expires_at: row.expires_at,
+            created_at: row.created_at,
+        }
     }
 }
 
+struct RoleGrantRow {
+    user_id: i32,
+    github_login: String,
+    organization: Option<String>,
+    role: String,
+}
+
+impl TryFrom<RoleGrantRow> for RoleGrant {
+    type Error = RepositoryError;
+
+    fn try_from(row: RoleGrantRow) -> Result<Self, Self::Error> {
+        Ok(RoleGrant {
+            user_id: row.user_id,
+            github_login: row.github_login,
+            organization: row.organization,
+            role: row
+                .role
+                .parse()
+                .map_err(|_| RepositoryError::FailedToGetRolesError)?,
+        })
+    }
+}
+
+struct SessionRow {
+    id: String,
+    user_id: i32,
+    access_token: String,
+    refresh_token: String,
+    token_key_id: Option<String>,
+    data_key: Option<String>,
+    expires_at: DateTime,
+    refresh_expires_at: DateTime,
+    last_used_at: DateTime,
+    access_checked_at: DateTime,
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum RepositoryError {
     FailedToCreateSessionError,
     FailedToCreateUserError,
+    FailedToUpdateSessionError,
+    FailedToGetProjectSettingsError,
+    FailedToSaveProjectSettingsError,
+    FailedToGetUserError,
+    FailedToRecordProjectViewError,
+    FailedToGetRecentProjectsError,
+    FailedToDeleteSessionError,
+    FailedToGetSessionsError,
+    FailedToEncryptTokensError,
+    FailedToDecryptTokensError,
+    FailedToCreateApiTokenError,
+    FailedToGetApiTokensError,
+    FailedToDeleteApiTokenError,
+    FailedToGetRolesError,
+    FailedToGrantRoleError,
+    FailedToRevokeRoleError,
 }
 impl Display for RepositoryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Failed to create session: Database insert failed")
+        match self {
+            RepositoryError::FailedToCreateSessionError => {
+                write!(f, "Failed to create session: Database insert failed")
+            }
+            RepositoryError::FailedToCreateUserError => {
+                write!(f, "Failed to create user: Database insert failed")
+            }
+            RepositoryError::FailedToUpdateSessionError => {
+                write!(f, "Failed to update session: Database update failed")
+            }
+            RepositoryError::FailedToGetProjectSettingsError => {
+                write!(f, "Failed to get project settings: Database query failed")
+            }
+            RepositoryError::FailedToSaveProjectSettingsError => {
+                write!(f, "Failed to save project settings: Database upsert failed")
+            }
+            RepositoryError::FailedToGetUserError => {
+                write!(f, "Failed to get user: Database query failed")
+            }
+            RepositoryError::FailedToRecordProjectViewError => {
+                write!(f, "Failed to record project view: Database upsert failed")
+            }
+            RepositoryError::FailedToGetRecentProjectsError => {
+                write!(f, "Failed to get recent projects: Database query failed")
+            }
+            RepositoryError::FailedToDeleteSessionError => {
+                write!(f, "Failed to delete session: Database delete failed")
+            }
+            RepositoryError::FailedToGetSessionsError => {
+                write!(f, "Failed to get sessions: Database query failed")
+            }
+            RepositoryError::FailedToEncryptTokensError => {
+                write!(f, "Failed to encrypt session tokens")
+            }
+            RepositoryError::FailedToDecryptTokensError => {
+                write!(f, "Failed to decrypt session tokens")
+            }
+            RepositoryError::FailedToCreateApiTokenError => {
+                write!(f, "Failed to create API token: Database insert failed")
+            }
+            RepositoryError::FailedToGetApiTokensError => {
+                write!(f, "Failed to get API tokens: Database query failed")
+            }
+            RepositoryError::FailedToDeleteApiTokenError => {
+                write!(f, "Failed to delete API token: Database delete failed")
+            }
+            RepositoryError::FailedToGetRolesError => {
+                write!(f, "Failed to get roles: Database query failed")
+            }
+            RepositoryError::FailedToGrantRoleError => {
+                write!(f, "Failed to grant role: Database upsert failed")
+            }
+            RepositoryError::FailedToRevokeRoleError => {
+                write!(f, "Failed to revoke role: Database delete failed")
+            }
+        }
     }
 }
 impl Error for RepositoryError {}
@@ -44,6 +269,80 @@ pub trait Repository: Send + Sync {
     ) -> Result<Session, RepositoryError>;
     async fn get_or_create_user(&self, login: &str) -> Result<User, RepositoryError>;
     async fn get_session(&self, session_id: Uuid) -> Result<Option<Session>, RepositoryError>;
+    async fn update_session_tokens(
+        &self,
+        session_id: Uuid,
+        update_session_tokens_request: UpdateSessionTokensRequest,
+    ) -> Result<Session, RepositoryError>;
+    async fn claim_session_refresh(
+        &self,
+        session_id: Uuid,
+        expires_at: DateTime,
+        stale_claims_before: DateTime,
+    ) -> Result<bool, RepositoryError>;
+    async fn get_project_settings(
+        &self,
+        owner: &str,
+        project_number: i32,
+    ) -> Result<Option<ProjectSettings>, RepositoryError>;
+    async fn save_project_settings(
+        &self,
+        project_settings: ProjectSettings,
+    ) -> Result<ProjectSettings, RepositoryError>;
+    async fn get_user(&self, user_id: i32) -> Result<Option<User>, RepositoryError>;
+    async fn record_project_view(
+        &self,
+        user_id: i32,
+        recent_project: RecentProject,
+    ) -> Result<(), RepositoryError>;
+    async fn get_recent_projects(
+        &self,
+        user_id: i32,
+        limit: i64,
+    ) -> Result<Vec<RecentProject>, RepositoryError>;
+    async fn delete_session(&self, session_id: Uuid) -> Result<(), RepositoryError>;
+    async fn touch_session(
+        &self,
+        session_id: Uuid,
+        touch_session_request: TouchSessionRequest,
+    ) -> Result<(), RepositoryError>;
+    async fn get_user_sessions(&self, user_id: i32) -> Result<Vec<SessionInfo>, RepositoryError>;
+    async fn delete_user_session(
+        &self,
+        user_id: i32,
+        session_id: Uuid,
+    ) -> Result<(), RepositoryError>;
+    async fn delete_user_sessions(&self, user_id: i32) -> Result<(), RepositoryError>;
+    async fn delete_stale_sessions(
+        &self,
+        expired_before: DateTime,
+        idle_before: DateTime,
+    ) -> Result<u64, RepositoryError>;
+    async fn mark_session_access_checked(
+        &self,
+        session_id: Uuid,
+        access_checked_at: DateTime,
+    ) -> Result<(), RepositoryError>;
+    async fn create_api_token(
+        &self,
+        create_api_token_request: CreateApiTokenRequest,
+    ) -> Result<ApiToken, RepositoryError>;
+    async fn get_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>, RepositoryError>;
+    async fn get_user_api_tokens(&self, user_id: i32) -> Result<Vec<ApiToken>, RepositoryError>;
+    async fn delete_user_api_token(
+        &self,
+        user_id: i32,
+        api_token_id: Uuid,
+    ) -> Result<(), RepositoryError>;
+    async fn get_user_roles(&self, user_id: i32) -> Result<Vec<RoleGrant>, RepositoryError>;
+    async fn get_role_grants(&self) -> Result<Vec<RoleGrant>, RepositoryError>;
+    async fn grant_role(&self, grant_role_request: GrantRoleRequest)
+    -> Result<(), RepositoryError>;
+    async fn revoke_role(
+        &self,
+        user_id: i32,
+        organization: Option<String>,
+    ) -> Result<(), RepositoryError>;
 }
 
 #[derive(PartialEq, Debug)]
@@ -52,6 +351,41 @@ pub struct CreateSessionRequest {
     pub access_token: String,
     pub refresh_token: String,
     pub expires_at: DateTime,
+    pub refresh_expires_at: DateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct UpdateSessionTokensRequest {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: DateTime,
+    pub refresh_expires_at: DateTime,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct CreateApiTokenRequest {
+    pub user_id: i32,
+    pub session_id: Uuid,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: DateTime,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct GrantRoleRequest {
+    pub user_id: i32,
+    pub organization: Option<String>,
+    pub role: Role,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct TouchSessionRequest {
+    pub last_used_at: DateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
 }
 
 #[async_trait::async_trait]
@@ -60,19 +394,36 @@ impl Repository for Postgres {
         &self,
         create_session_request: CreateSessionRequest,
     ) -> Result<Session, RepositoryError> {
-        sqlx::query_as!(
-            Session,
-            "INSERT INTO sessions(user_id, access_token, refresh_token, expires_at) 
-            VALUES ($1, $2, $3, $4) 
-            RETURNING id, user_id,  access_token, refresh_token, expires_at",
+        // The id is generated up front because the encrypted tokens are bound to it.
+        let session_id = Builder::from_random_bytes(rand::random()).into_uuid();
+        let encrypted_tokens = self
+            .token_keys
+            .encrypt(
+                &session_id.to_string(),
+                &create_session_request.access_token,
+                &create_session_request.refresh_token,
+            )
+            .map_err(|_| RepositoryError::FailedToEncryptTokensError)?;
+        let row = sqlx::query_as!(
+            SessionRow,
+            "INSERT INTO sessions(id, user_id, access_token, refresh_token, token_key_id, data_key, expires_at, refresh_expires_at, user_agent, ip_address) 
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) 
+            RETURNING id, user_id, access_token, refresh_token, token_key_id, data_key, expires_at, refresh_expires_at, last_used_at, access_checked_at",
+            session_id,
             create_session_request.user_id,
-            create_session_request.access_token,
-            create_session_request.refresh_token,
-            create_session_request.expires_at
+            encrypted_tokens.access_token,
+            encrypted_tokens.refresh_token,
+            encrypted_tokens.key_id,
+            encrypted_tokens.data_key,
+            create_session_request.expires_at,
+            create_session_request.refresh_expires_at,
+            create_session_request.user_agent,
+            create_session_request.ip_address
         )
         .fetch_one(&self.pool)
         .await
-        .map_err(|_| RepositoryError::FailedToCreateSessionError)
+        .map_err(|_| RepositoryError::FailedToCreateSessionError)?;
+        self.decrypt_session(row)
     }
     async fn get_or_create_user(&self, login: &str) -> Result<User, RepositoryError> {
         sqlx::query_as!(
@@ -88,15 +439,397 @@ impl Repository for Postgres {
         .map_err(|_| RepositoryError::FailedToCreateUserError)
     }
     async fn get_session(&self, session_id: Uuid) -> Result<Option<Session>, RepositoryError> {
-        sqlx::query_as!(
-            Session,
-            "SELECT id, user_id, access_token, refresh_token, expires_at
+        let row = sqlx::query_as!(
+            SessionRow,
+            "SELECT id, user_id, access_token, refresh_token, token_key_id, data_key, expires_at, refresh_expires_at, last_used_at, access_checked_at
             FROM sessions
             WHERE id = $1",
             session_id
         )
         .fetch_optional(&self.pool)
         .await
-        .map_err(|_| RepositoryError::FailedToCreateSessionError)
+        .map_err(|_| RepositoryError::FailedToCreateSessionError)?;
+        match row {
+            Some(row) if row.token_key_id.is_none() => {
+                self.encrypt_plaintext_session(row).await.map(Some)
+            }
+            row => row.map(|row| self.decrypt_session(row)).transpose(),
+        }
+    }
+    async fn update_session_tokens(
+        &self,
+        session_id: Uuid,
+        update_session_tokens_request: UpdateSessionTokensRequest,
+    ) -> Result<Session, RepositoryError> {
+        let encrypted_tokens = self
+            .token_keys
+            .encrypt(
+                &session_id.to_string(),
+                &update_session_tokens_request.access_token,
+                &update_session_tokens_request.refresh_token,
+            )
+            .map_err(|_| RepositoryError::FailedToEncryptTokensError)?;
+        let row = sqlx::query_as!(
+            SessionRow,
+            "UPDATE sessions
+            SET access_token = $2, refresh_token = $3, token_key_id = $4, data_key = $5, expires_at = $6, refresh_expires_at = $7, refresh_claimed_at = NULL, updated_at = now()
+            WHERE id = $1
+            RETURNING id, user_id, access_token, refresh_token, token_key_id, data_key, expires_at, refresh_expires_at, last_used_at, access_checked_at",
+            session_id,
+            encrypted_tokens.access_token,
+            encrypted_tokens.refresh_token,
+            encrypted_tokens.key_id,
+            encrypted_tokens.data_key,
+            update_session_tokens_request.expires_at,
+            update_session_tokens_request.refresh_expires_at
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToUpdateSessionError)?;
+        self.decrypt_session(row)
+    }
+    async fn claim_session_refresh(
+        &self,
+        session_id: Uuid,
+        expires_at: DateTime,
+        stale_claims_before: DateTime,
+    ) -> Result<bool, RepositoryError> {
+        let result = sqlx::query!(
+            "UPDATE sessions
+            SET refresh_claimed_at = now()
+            WHERE id = $1 AND expires_at = $2
+            AND (refresh_claimed_at IS NULL OR refresh_claimed_at < $3)",
+            session_id,
+            expires_at,
+            stale_claims_before
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToUpdateSessionError)?;
+        Ok(result.rows_affected() == 1)
+    }
+    async fn get_project_settings(
+        &self,
+        owner: &str,
+        project_number: i32,
+    ) -> Result<Option<ProjectSettings>, RepositoryError> {
+        let row = sqlx::query!(
+            r#"SELECT owner, project_number, required_fields as "required_fields: Json<Vec<RequiredField>>"
+            FROM project_settings
+            WHERE owner = $1 AND project_number = $2"#,
+            owner,
+            project_number
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetProjectSettingsError)?;
+        Ok(row.map(|row| ProjectSettings {
+            owner: row.owner,
+            project_number: row.project_number,
+            required_fields: row.required_fields.0,
+        }))
+    }
+    async fn save_project_settings(
+        &self,
+        project_settings: ProjectSettings,
+    ) -> Result<ProjectSettings, RepositoryError> {
+        let row = sqlx::query!(
+            r#"INSERT INTO project_settings(owner, project_number, required_fields)
+            VALUES ($1, $2, $3)
+            ON CONFLICT(owner, project_number)
+            DO UPDATE SET required_fields = EXCLUDED.required_fields, updated_at = now()
+            RETURNING owner, project_number, required_fields as "required_fields: Json<Vec<RequiredField>>""#,
+            project_settings.owner,
+            project_settings.project_number,
+            Json(project_settings.required_fields) as _
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToSaveProjectSettingsError)?;
+        Ok(ProjectSettings {
+            owner: row.owner,
+            project_number: row.project_number,
+            required_fields: row.required_fields.0,
+        })
+    }
+    async fn get_user(&self, user_id: i32) -> Result<Option<User>, RepositoryError> {
+        sqlx::query_as!(
+            User,
+            "SELECT id, github_login
+            FROM users
+            WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetUserError)
+    }
+    async fn record_project_view(
+        &self,
+        user_id: i32,
+        recent_project: RecentProject,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "INSERT INTO recent_projects(user_id, owner_kind, owner, project_number, viewed_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT(user_id, owner_kind, owner, project_number)
+            DO UPDATE SET viewed_at = EXCLUDED.viewed_at",
+            user_id,
+            recent_project.owner_kind,
+            recent_project.owner,
+            recent_project.project_number,
+            recent_project.viewed_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToRecordProjectViewError)?;
+        Ok(())
+    }
+    async fn get_recent_projects(
+        &self,
+        user_id: i32,
+        limit: i64,
+    ) -> Result<Vec<RecentProject>, RepositoryError> {
+        sqlx::query_as!(
+            RecentProject,
+            "SELECT owner_kind, owner, project_number, viewed_at
+            FROM recent_projects
+            WHERE user_id = $1
+            ORDER BY viewed_at DESC
+            LIMIT $2",
+            user_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetRecentProjectsError)
+    }
+    async fn delete_session(&self, session_id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| RepositoryError::FailedToDeleteSessionError)?;
+        Ok(())
+    }
+    async fn touch_session(
+        &self,
+        session_id: Uuid,
+        touch_session_request: TouchSessionRequest,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "UPDATE sessions
+            SET last_used_at = $2,
+                user_agent = COALESCE($3, user_agent),
+                ip_address = COALESCE($4, ip_address)
+            WHERE id = $1",
+            session_id,
+            touch_session_request.last_used_at,
+            touch_session_request.user_agent,
+            touch_session_request.ip_address
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToUpdateSessionError)?;
+        Ok(())
+    }
+    async fn get_user_sessions(&self, user_id: i32) -> Result<Vec<SessionInfo>, RepositoryError> {
+        sqlx::query_as!(
+            SessionInfo,
+            "SELECT id, created_at, last_used_at, user_agent, ip_address
+            FROM sessions
+            WHERE user_id = $1
+            ORDER BY last_used_at DESC",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetSessionsError)
+    }
+    async fn delete_user_session(
+        &self,
+        user_id: i32,
+        session_id: Uuid,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE id = $1 AND user_id = $2",
+            session_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToDeleteSessionError)?;
+        Ok(())
+    }
+    async fn delete_user_sessions(&self, user_id: i32) -> Result<(), RepositoryError> {
+        sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| RepositoryError::FailedToDeleteSessionError)?;
+        Ok(())
+    }
+    async fn delete_stale_sessions(
+        &self,
+        expired_before: DateTime,
+        idle_before: DateTime,
+    ) -> Result<u64, RepositoryError> {
+        let result = sqlx::query!(
+            "DELETE FROM sessions
+            WHERE refresh_expires_at < $1
+            OR (
+                last_used_at < $2
+                AND NOT EXISTS (
+                    SELECT 1 FROM api_tokens
+                    WHERE api_tokens.session_id = sessions.id AND api_tokens.expires_at > $1
+                )
+            )",
+            expired_before,
+            idle_before
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToDeleteSessionError)?;
+        Ok(result.rows_affected())
+    }
+    async fn mark_session_access_checked(
+        &self,
+        session_id: Uuid,
+        access_checked_at: DateTime,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "UPDATE sessions SET access_checked_at = $2 WHERE id = $1",
+            session_id,
+            access_checked_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToUpdateSessionError)?;
+        Ok(())
+    }
+    async fn create_api_token(
+        &self,
+        create_api_token_request: CreateApiTokenRequest,
+    ) -> Result<ApiToken, RepositoryError> {
+        let scopes: Vec<String> = create_api_token_request
+            .scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect();
+        sqlx::query_as!(
+            ApiTokenRow,
+            "INSERT INTO api_tokens(user_id, session_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, session_id, name, scopes, expires_at, created_at",
+            create_api_token_request.user_id,
+            create_api_token_request.session_id,
+            create_api_token_request.name,
+            create_api_token_request.token_hash,
+            &scopes,
+            create_api_token_request.expires_at
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map(ApiToken::from)
+        .map_err(|_| RepositoryError::FailedToCreateApiTokenError)
+    }
+    async fn get_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>, RepositoryError> {
+        sqlx::query_as!(
+            ApiTokenRow,
+            "SELECT id, user_id, session_id, name, scopes, expires_at, created_at
+            FROM api_tokens
+            WHERE token_hash = $1",
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(ApiToken::from))
+        .map_err(|_| RepositoryError::FailedToGetApiTokensError)
+    }
+    async fn get_user_api_tokens(&self, user_id: i32) -> Result<Vec<ApiToken>, RepositoryError> {
+        sqlx::query_as!(
+            ApiTokenRow,
+            "SELECT id, user_id, session_id, name, scopes, expires_at, created_at
+            FROM api_tokens
+            WHERE user_id = $1
+            ORDER BY created_at DESC",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().map(ApiToken::from).collect())
+        .map_err(|_| RepositoryError::FailedToGetApiTokensError)
+    }
+    async fn delete_user_api_token(
+        &self,
+        user_id: i32,
+        api_token_id: Uuid,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "DELETE FROM api_tokens WHERE id = $1 AND user_id = $2",
+            api_token_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToDeleteApiTokenError)?;
+        Ok(())
+    }
+    async fn get_user_roles(&self, user_id: i32) -> Result<Vec<RoleGrant>, RepositoryError> {
+        let rows = sqlx::query_as!(
+            RoleGrantRow,
+            "SELECT user_roles.user_id, users.github_login, user_roles.organization, user_roles.role
+            FROM user_roles
+            JOIN users ON users.id = user_roles.user_id
+            WHERE user_roles.user_id = $1",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetRolesError)?;
+        rows.into_iter().map(RoleGrant::try_from).collect()
+    }
+    async fn get_role_grants(&self) -> Result<Vec<RoleGrant>, RepositoryError> {
+        let rows = sqlx::query_as!(
+            RoleGrantRow,
+            "SELECT user_roles.user_id, users.github_login, user_roles.organization, user_roles.role
+            FROM user_roles
+            JOIN users ON users.id = user_roles.user_id
+            ORDER BY users.github_login, user_roles.organization NULLS FIRST"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGetRolesError)?;
+        rows.into_iter().map(RoleGrant::try_from).collect()
+    }
+    async fn grant_role(
+        &self,
+        grant_role_request: GrantRoleRequest,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "INSERT INTO user_roles(user_id, organization, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, COALESCE(organization, '')) DO UPDATE SET role = EXCLUDED.role",
+            grant_role_request.user_id,
+            grant_role_request.organization,
+            grant_role_request.role.as_str()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToGrantRoleError)?;
+        Ok(())
+    }
+    async fn revoke_role(
+        &self,
+        user_id: i32,
+        organization: Option<String>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "DELETE FROM user_roles WHERE user_id = $1 AND organization IS NOT DISTINCT FROM $2",
+            user_id,
+            organization
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| RepositoryError::FailedToRevokeRoleError)?;
+        Ok(())
     }
 }
@@ -1,10 +1,18 @@
 use std::sync::Arc;
 
-use axum::{Router, routing::get};
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
 
 use crate::{
     app::AppState,
-    controller::{callback, get_unmapped_items, login},
+    controller::{
+        callback, create_api_token, get_project_fields, get_project_settings, get_unmapped_items,
+        grant_role, home, list_api_tokens, list_projects, list_roles, list_sessions, login, logout,
+        render_errors, revoke_all_sessions, revoke_api_token, revoke_role, revoke_session,
+        update_item_field, update_items_field, update_project_settings,
+    },
     github::GithubService,
     repository::Postgres,
 };
@@ -12,8 +20,38 @@ use crate::{
 pub fn get_router(shared_state: Arc<AppState<GithubService, Postgres>>) -> axum::Router {
     let router = Router::new();
     router
+        .route("/", get(home))
         .route("/login", get(login))
         .route("/callback", get(callback))
-        .route("/org/{org}/project/{id}", get(get_unmapped_items))
+        .route("/logout", post(logout))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/revoke", post(revoke_all_sessions))
+        .route("/sessions/{session_id}/revoke", post(revoke_session))
+        .route("/tokens", get(list_api_tokens).post(create_api_token))
+        .route("/tokens/{api_token_id}/revoke", post(revoke_api_token))
+        .route("/admin/roles", get(list_roles).post(grant_role))
+        .route("/admin/roles/revoke", post(revoke_role))
+        .route("/{owner_kind}/{owner}/projects", get(list_projects))
+        .route(
+            "/{owner_kind}/{owner}/project/{id}",
+            get(get_unmapped_items),
+        )
+        .route(
+            "/{owner_kind}/{owner}/project/{id}/fields",
+            get(get_project_fields),
+        )
+        .route(
+            "/{owner_kind}/{owner}/project/{id}/items/field",
+            post(update_items_field),
+        )
+        .route(
+            "/{owner_kind}/{owner}/project/{id}/items/{item_id}/field",
+            post(update_item_field),
+        )
+        .route(
+            "/{owner_kind}/{owner}/project/{id}/settings",
+            get(get_project_settings).put(update_project_settings),
+        )
+        .layer(middleware::from_fn(render_errors))
         .with_state(shared_state)
 }
@@ -0,0 +1,111 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    app::AppState,
+    controller::{AppError, CLIENT_ID, load_project_settings},
+    github::{Github, OwnerKind},
+    repository::Repository,
+};
+
+pub async fn scan_projects_every<T: Github, U: Repository>(
+    state: Arc<AppState<T, U>>,
+    scan_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(scan_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        for organization in &state.config.installation_organizations {
+            match scan_organization(&state, organization).await {
+                Ok(unmapped_items) => tracing::info!(
+                    "Project scan found {} unmapped items in {}",
+                    unmapped_items,
+                    organization
+                ),
+                Err(e) => tracing::warn!("Project scan of {} failed: {}", organization, e),
+            }
+        }
+    }
+}
+
+// Reads the open projects of an organization as the app installation, no user has to be logged in.
+async fn scan_organization<T: Github, U: Repository>(
+    state: &AppState<T, U>,
+    organization: &str,
+) -> Result<usize, AppError> {
+    let access_token = state
+        .github
+        .get_installation_token(
+            CLIENT_ID,
+            &state.secrets.github_app_private_key,
+            organization,
+        )
+        .await?;
+    let project_list = state
+        .github
+        .list_projects(
+            OwnerKind::Organization,
+            organization.to_string(),
+            &access_token,
+        )
+        .await?;
+    let mut unmapped_items = 0;
+    for project in project_list
+        .projects
+        .iter()
+        .filter(|project| !project.closed)
+    {
+        let project_id = u32::try_from(project.number).map_err(|_| {
+            AppError::Internal(format!("Invalid project number {}", project.number))
+        })?;
+        let project_settings =
+            load_project_settings(&state.repository, organization, project_id).await?;
+        let project_items = state
+            .github
+            .get_unmapped_items(
+                OwnerKind::Organization,
+                organization.to_string(),
+                project_id,
+                &project_settings.required_fields,
+                &access_token,
+            )
+            .await?;
+        tracing::info!(
+            "Project {} of {} has {} unmapped items",
+            project_id,
+            organization,
+            project_items.items.len()
+        );
+        unmapped_items += project_items.items.len();
+    }
+    Ok(unmapped_items)
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::eq;
+
+    use crate::{
+        app::AppConfig, controller::AppError, fixtures::test_state, repository::MockRepository,
+        scanner::scan_organization,
+    };
+
+    #[tokio::test]
+    async fn scan_counts_unmapped_items_of_installed_organizations() {
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_get_project_settings()
+            .with(eq("acme"), eq(1))
+            .times(1)
+            .returning(|_, _| Ok(None));
+        let state = test_state(AppConfig::default(), repository_mock);
+
+        assert_eq!(scan_organization(&state, "acme").await.unwrap(), 1);
+        assert!(matches!(
+            scan_organization(&state, "uninstalled_org").await,
+            Err(AppError::GithubStatus(_))
+        ));
+    }
+}
@@ -24,6 +24,16 @@ where
     String::from_utf8(bytes).map_err(|e| de::Error::custom(format!("Failed to utf8 decode: {}", e)))
 }
 
+pub fn b64_to_bytes<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let encoded = String::deserialize(deserializer)?;
+    BASE64_STANDARD
+        .decode(&encoded)
+        .map_err(|e| de::Error::custom(format!("Failed to decode secrets: {}", e)))
+}
+
 const SECRETS: &str = "secrets.yaml";
 impl Secrets {
     pub fn load<T>() -> T
@@ -0,0 +1,54 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{TimeDelta, Utc};
+use tokio::time::MissedTickBehavior;
+
+use crate::{app::AppState, github::Github, repository::Repository};
+
+static SESSION_IDLE_TIMEOUT: TimeDelta = TimeDelta::days(30);
+
+pub async fn sweep_sessions_every<T: Github, U: Repository>(
+    state: Arc<AppState<T, U>>,
+    sweep_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(sweep_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        sweep_sessions(&state.repository).await;
+    }
+}
+
+// Sessions with an expired access token are kept until their refresh token expires too.
+async fn sweep_sessions<U: Repository>(repository: &U) {
+    let now = Utc::now();
+    match repository
+        .delete_stale_sessions(now, now - SESSION_IDLE_TIMEOUT)
+        .await
+    {
+        Ok(deleted) => tracing::info!("Session sweep deleted {} stale sessions", deleted),
+        Err(e) => tracing::warn!("Session sweep failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+
+    use crate::{repository::MockRepository, sweeper::sweep_sessions};
+
+    #[tokio::test]
+    async fn sweep_deletes_expired_and_idle_sessions() {
+        let mut repository_mock = MockRepository::new();
+        repository_mock
+            .expect_delete_stale_sessions()
+            .withf(|expired_before, idle_before| {
+                *expired_before <= Utc::now()
+                    && *expired_before - *idle_before == TimeDelta::days(30)
+            })
+            .times(1)
+            .returning(|_, _| Ok(3));
+
+        sweep_sessions(&repository_mock).await;
+    }
+}